@@ -0,0 +1,706 @@
+//! Round-trippable text representation of Standard MIDI Files.
+//!
+//! The format is line-based, with `#` starting a comment that runs until the end of the line. The
+//! first line describes the SMF header:
+//!
+//!     smf format=1 ppqn=480
+//!     smf format=0 fps=29.97 subframes=80
+//!
+//! Every `track` line starts a new track. Each following line describes one event as its delta
+//! time, its type, any `key=value` parameters, and, for events that carry arbitrary data, the data
+//! bytes as any sequence of two-digit hexadecimal values and quoted strings:
+//!
+//!     track
+//!     +0 track_name "Piano"
+//!     +0 program_change channel=1 program=0
+//!     +0 sys_ex 7E 7F 09 01 F7
+//!     +240 note_on channel=1 key=60 vel=100
+//!     +240 note_off channel=1 key=60 vel=64
+//!     +0 end_of_track
+//!
+//! Event types and parameter names match the JSON output of the `dump` command. Quoted strings are
+//! encoded as UTF-8, and support the `\"`, `\\`, `\n`, `\r`, `\t`, and `\xNN` escapes for arbitrary
+//! bytes.
+
+use std::{error::Error, fmt::Write, io};
+
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, Fps, Header, MetaMessage, MidiMessage, PitchBend, Smf, SmpteTime, Timing, TrackEvent,
+    TrackEventKind,
+};
+
+pub fn fps_name(fps: Fps) -> &'static str {
+    match fps {
+        Fps::Fps24 => "24",
+        Fps::Fps25 => "25",
+        Fps::Fps29 => "29.97",
+        Fps::Fps30 => "30",
+    }
+}
+
+/// Appends `bytes` as space-separated hexadecimal values.
+fn write_hex(line: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        write!(line, " {byte:02X}").unwrap();
+    }
+}
+
+/// Appends `bytes` as a quoted string, escaping any bytes that are not printable UTF-8.
+fn write_string(line: &mut String, bytes: &[u8]) {
+    line.push_str(" \"");
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => line.push_str("\\\""),
+                '\\' => line.push_str("\\\\"),
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                '\t' => line.push_str("\\t"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        write!(line, "\\x{byte:02X}").unwrap();
+                    }
+                }
+                c => line.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            write!(line, "\\x{byte:02X}").unwrap();
+        }
+    }
+    line.push('"');
+}
+
+/// Appends the event type `name` and the given `key=value` parameters.
+fn write_params(line: &mut String, name: &str, values: &[(&str, &dyn std::fmt::Display)]) {
+    line.push_str(name);
+    for (key, value) in values {
+        write!(line, " {key}={value}").unwrap();
+    }
+}
+
+fn event_line(kind: &TrackEventKind) -> String {
+    let mut line = String::new();
+    match *kind {
+        TrackEventKind::Midi { channel, message } => {
+            let ch = channel.as_int() + 1;
+            match message {
+                MidiMessage::NoteOff { key, vel } => write_params(
+                    &mut line,
+                    "note_off",
+                    &[("channel", &ch), ("key", &key), ("vel", &vel)],
+                ),
+                MidiMessage::NoteOn { key, vel } => write_params(
+                    &mut line,
+                    "note_on",
+                    &[("channel", &ch), ("key", &key), ("vel", &vel)],
+                ),
+                MidiMessage::Aftertouch { key, vel } => write_params(
+                    &mut line,
+                    "aftertouch",
+                    &[("channel", &ch), ("key", &key), ("vel", &vel)],
+                ),
+                MidiMessage::Controller { controller, value } => write_params(
+                    &mut line,
+                    "controller",
+                    &[
+                        ("channel", &ch),
+                        ("controller", &controller),
+                        ("value", &value),
+                    ],
+                ),
+                MidiMessage::ProgramChange { program } => write_params(
+                    &mut line,
+                    "program_change",
+                    &[("channel", &ch), ("program", &program)],
+                ),
+                MidiMessage::ChannelAftertouch { vel } => write_params(
+                    &mut line,
+                    "channel_aftertouch",
+                    &[("channel", &ch), ("vel", &vel)],
+                ),
+                MidiMessage::PitchBend { bend } => write_params(
+                    &mut line,
+                    "pitch_bend",
+                    &[("channel", &ch), ("bend", &bend.as_int())],
+                ),
+            }
+        }
+        TrackEventKind::SysEx(data) => {
+            line.push_str("sys_ex");
+            write_hex(&mut line, data);
+        }
+        TrackEventKind::Escape(data) => {
+            line.push_str("escape");
+            write_hex(&mut line, data);
+        }
+        TrackEventKind::Meta(meta) => match meta {
+            MetaMessage::TrackNumber(None) => write_params(&mut line, "track_number", &[]),
+            MetaMessage::TrackNumber(Some(number)) => {
+                write_params(&mut line, "track_number", &[("number", &number)])
+            }
+            MetaMessage::Text(data)
+            | MetaMessage::Copyright(data)
+            | MetaMessage::TrackName(data)
+            | MetaMessage::InstrumentName(data)
+            | MetaMessage::Lyric(data)
+            | MetaMessage::Marker(data)
+            | MetaMessage::CuePoint(data)
+            | MetaMessage::ProgramName(data)
+            | MetaMessage::DeviceName(data) => {
+                line.push_str(match meta {
+                    MetaMessage::Text(_) => "text",
+                    MetaMessage::Copyright(_) => "copyright",
+                    MetaMessage::TrackName(_) => "track_name",
+                    MetaMessage::InstrumentName(_) => "instrument_name",
+                    MetaMessage::Lyric(_) => "lyric",
+                    MetaMessage::Marker(_) => "marker",
+                    MetaMessage::CuePoint(_) => "cue_point",
+                    MetaMessage::ProgramName(_) => "program_name",
+                    _ => "device_name",
+                });
+                write_string(&mut line, data);
+            }
+            MetaMessage::MidiChannel(channel) => write_params(
+                &mut line,
+                "midi_channel",
+                &[("channel", &(channel.as_int() + 1))],
+            ),
+            MetaMessage::MidiPort(port) => write_params(&mut line, "midi_port", &[("port", &port)]),
+            MetaMessage::EndOfTrack => write_params(&mut line, "end_of_track", &[]),
+            MetaMessage::Tempo(tempo) => write_params(&mut line, "tempo", &[("qn_micros", &tempo)]),
+            MetaMessage::SmpteOffset(smpte) => write_params(
+                &mut line,
+                "smpte_offset",
+                &[
+                    ("fps", &fps_name(smpte.fps())),
+                    ("hour", &smpte.hour()),
+                    ("minute", &smpte.minute()),
+                    ("second", &smpte.second()),
+                    ("frame", &smpte.frame()),
+                    ("subframe", &smpte.subframe()),
+                ],
+            ),
+            MetaMessage::TimeSignature(numerator, denominator_pow2, clocks, notes_32nd) => {
+                write_params(
+                    &mut line,
+                    "time_signature",
+                    &[
+                        ("numerator", &numerator),
+                        ("denominator_pow2", &denominator_pow2),
+                        ("clocks_per_click", &clocks),
+                        ("notes_32nd_per_qn", &notes_32nd),
+                    ],
+                )
+            }
+            MetaMessage::KeySignature(sharps, minor) => write_params(
+                &mut line,
+                "key_signature",
+                &[("sharps", &sharps), ("minor", &minor)],
+            ),
+            MetaMessage::SequencerSpecific(data) => {
+                line.push_str("sequencer_specific");
+                write_hex(&mut line, data);
+            }
+            MetaMessage::Unknown(meta_type, data) => {
+                write_params(&mut line, "unknown_meta", &[("meta_type", &meta_type)]);
+                write_hex(&mut line, data);
+            }
+        },
+    }
+    line
+}
+
+/// Returns `smf` in the text format described at the top of this module.
+fn disassembly(smf: &Smf) -> String {
+    let format = match smf.header.format {
+        Format::SingleTrack => 0,
+        Format::Parallel => 1,
+        Format::Sequential => 2,
+    };
+    let mut ret = match smf.header.timing {
+        Timing::Metrical(ppqn) => format!("smf format={format} ppqn={ppqn}\n"),
+        Timing::Timecode(fps, subframes) => format!(
+            "smf format={format} fps={} subframes={subframes}\n",
+            fps_name(fps)
+        ),
+    };
+    for (track_i, track) in smf.tracks.iter().enumerate() {
+        writeln!(ret, "\ntrack # {track_i}").unwrap();
+        for ev in track {
+            writeln!(ret, "{:+} {}", ev.delta, event_line(&ev.kind)).unwrap();
+        }
+    }
+    ret
+}
+
+/// Prints `smf` in the text format described at the top of this module.
+pub fn disassemble(smf: &Smf) {
+    print!("{}", disassembly(smf));
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Param(String, String),
+    Bytes(Vec<u8>),
+}
+
+/// Splits a line into tokens, stripping any comment.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut ret = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut bytes = vec![];
+            loop {
+                let c = chars.next().ok_or("unterminated string")?;
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next().ok_or("unterminated string")? {
+                        '"' => bytes.push(b'"'),
+                        '\\' => bytes.push(b'\\'),
+                        'n' => bytes.push(b'\n'),
+                        'r' => bytes.push(b'\r'),
+                        't' => bytes.push(b'\t'),
+                        'x' => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            let byte = u8::from_str_radix(&hex, 16)
+                                .map_err(|_| format!("invalid escape `\\x{hex}`"))?;
+                            bytes.push(byte);
+                        }
+                        c => return Err(format!("invalid escape `\\{c}`")),
+                    },
+                    c => {
+                        let mut buf = [0; 4];
+                        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                }
+            }
+            ret.push(Token::Bytes(bytes));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '#' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            ret.push(match word.split_once('=') {
+                Some((key, value)) => Token::Param(key.to_string(), value.to_string()),
+                None => Token::Word(word),
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// Parameters and data of a single line.
+struct Args {
+    params: Vec<(String, String)>,
+    data: Vec<u8>,
+}
+
+impl Args {
+    fn new(tokens: impl Iterator<Item = Token>) -> Result<Self, String> {
+        let mut ret = Args {
+            params: vec![],
+            data: vec![],
+        };
+        for token in tokens {
+            match token {
+                Token::Param(key, value) => {
+                    if ret.params.iter().any(|(other, _)| *other == key) {
+                        return Err(format!("duplicate parameter `{key}`"));
+                    }
+                    ret.params.push((key, value));
+                }
+                Token::Bytes(bytes) => ret.data.extend(bytes),
+                Token::Word(word) => {
+                    let byte = (word.len() == 2)
+                        .then(|| u8::from_str_radix(&word, 16).ok())
+                        .flatten()
+                        .ok_or_else(|| format!("`{word}` is not a hexadecimal byte"))?;
+                    ret.data.push(byte);
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn take_str(&mut self, key: &str) -> Option<String> {
+        let i = self.params.iter().position(|(other, _)| other == key)?;
+        Some(self.params.remove(i).1)
+    }
+
+    fn take<T: std::str::FromStr>(&mut self, key: &str) -> Result<T, String> {
+        let value = self
+            .take_str(key)
+            .ok_or_else(|| format!("missing parameter `{key}`"))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value for `{key}`: `{value}`"))
+    }
+
+    fn take_ranged<T, U: std::str::FromStr>(
+        &mut self,
+        key: &str,
+        convert: impl FnOnce(U) -> Option<T>,
+    ) -> Result<T, String> {
+        let value = self.take::<U>(key)?;
+        convert(value).ok_or_else(|| format!("value for `{key}` is out of range"))
+    }
+
+    fn take_u7(&mut self, key: &str) -> Result<u7, String> {
+        self.take_ranged(key, u7::try_from)
+    }
+
+    fn take_channel(&mut self) -> Result<u4, String> {
+        self.take_ranged("channel", |ch: u8| ch.checked_sub(1).and_then(u4::try_from))
+    }
+
+    /// Ensures that all parameters were consumed, and that there is no data if `has_data` is
+    /// `false`. Returns the data.
+    fn finish(self, has_data: bool) -> Result<Vec<u8>, String> {
+        if let Some((key, _)) = self.params.first() {
+            return Err(format!("unknown parameter `{key}`"));
+        }
+        if !has_data && !self.data.is_empty() {
+            return Err("unexpected data".into());
+        }
+        Ok(self.data)
+    }
+}
+
+fn parse_header(mut args: Args) -> Result<Header, String> {
+    let format = match args.take::<u8>("format")? {
+        0 => Format::SingleTrack,
+        1 => Format::Parallel,
+        2 => Format::Sequential,
+        _ => return Err("SMF format must be 0, 1, or 2".into()),
+    };
+    let timing = if args.params.iter().any(|(key, _)| key == "ppqn") {
+        Timing::Metrical(args.take_ranged("ppqn", |ppqn: u16| {
+            u15::try_from(ppqn).filter(|ppqn| ppqn.as_int() != 0)
+        })?)
+    } else {
+        let fps = parse_fps(args.take_str("fps").as_deref())?;
+        Timing::Timecode(fps, args.take("subframes")?)
+    };
+    args.finish(false)?;
+    Ok(Header::new(format, timing))
+}
+
+fn parse_fps(value: Option<&str>) -> Result<Fps, String> {
+    match value {
+        Some("24") => Ok(Fps::Fps24),
+        Some("25") => Ok(Fps::Fps25),
+        Some("29.97") => Ok(Fps::Fps29),
+        Some("30") => Ok(Fps::Fps30),
+        Some(fps) => Err(format!("unsupported FPS value `{fps}`")),
+        None => Err("missing parameter `fps`".into()),
+    }
+}
+
+/// Parses the event type and parameters of a line. Any data is inserted later by `with_data()`.
+fn parse_kind(name: &str, args: &mut Args) -> Result<(TrackEventKind<'static>, bool), String> {
+    let message = match name {
+        "note_off" => Some(MidiMessage::NoteOff {
+            key: args.take_u7("key")?,
+            vel: args.take_u7("vel")?,
+        }),
+        "note_on" => Some(MidiMessage::NoteOn {
+            key: args.take_u7("key")?,
+            vel: args.take_u7("vel")?,
+        }),
+        "aftertouch" => Some(MidiMessage::Aftertouch {
+            key: args.take_u7("key")?,
+            vel: args.take_u7("vel")?,
+        }),
+        "controller" => Some(MidiMessage::Controller {
+            controller: args.take_u7("controller")?,
+            value: args.take_u7("value")?,
+        }),
+        "program_change" => Some(MidiMessage::ProgramChange {
+            program: args.take_u7("program")?,
+        }),
+        "channel_aftertouch" => Some(MidiMessage::ChannelAftertouch {
+            vel: args.take_u7("vel")?,
+        }),
+        "pitch_bend" => Some(MidiMessage::PitchBend {
+            bend: args.take_ranged("bend", |bend: i16| {
+                (-0x2000..0x2000)
+                    .contains(&bend)
+                    .then(|| PitchBend::from_int(bend))
+            })?,
+        }),
+        _ => None,
+    };
+    if let Some(message) = message {
+        let channel = args.take_channel()?;
+        return Ok((TrackEventKind::Midi { channel, message }, false));
+    }
+
+    let meta = match name {
+        "sys_ex" => return Ok((TrackEventKind::SysEx(&[]), true)),
+        "escape" => return Ok((TrackEventKind::Escape(&[]), true)),
+        "track_number" => MetaMessage::TrackNumber(match args.take_str("number") {
+            Some(n) => Some(
+                n.parse()
+                    .map_err(|_| format!("invalid track number `{n}`"))?,
+            ),
+            None => None,
+        }),
+        "text" => MetaMessage::Text(&[]),
+        "copyright" => MetaMessage::Copyright(&[]),
+        "track_name" => MetaMessage::TrackName(&[]),
+        "instrument_name" => MetaMessage::InstrumentName(&[]),
+        "lyric" => MetaMessage::Lyric(&[]),
+        "marker" => MetaMessage::Marker(&[]),
+        "cue_point" => MetaMessage::CuePoint(&[]),
+        "program_name" => MetaMessage::ProgramName(&[]),
+        "device_name" => MetaMessage::DeviceName(&[]),
+        "midi_channel" => MetaMessage::MidiChannel(args.take_channel()?),
+        "midi_port" => MetaMessage::MidiPort(args.take_u7("port")?),
+        "end_of_track" => MetaMessage::EndOfTrack,
+        "tempo" => MetaMessage::Tempo(args.take_ranged("qn_micros", u24::try_from)?),
+        "smpte_offset" => {
+            let fps = parse_fps(args.take_str("fps").as_deref())?;
+            MetaMessage::SmpteOffset(
+                SmpteTime::new(
+                    args.take("hour")?,
+                    args.take("minute")?,
+                    args.take("second")?,
+                    args.take("frame")?,
+                    args.take("subframe")?,
+                    fps,
+                )
+                .ok_or("SMPTE offset is out of range")?,
+            )
+        }
+        "time_signature" => MetaMessage::TimeSignature(
+            args.take("numerator")?,
+            args.take("denominator_pow2")?,
+            args.take("clocks_per_click")?,
+            args.take("notes_32nd_per_qn")?,
+        ),
+        "key_signature" => MetaMessage::KeySignature(args.take("sharps")?, args.take("minor")?),
+        "sequencer_specific" => MetaMessage::SequencerSpecific(&[]),
+        "unknown_meta" => MetaMessage::Unknown(args.take("meta_type")?, &[]),
+        name => return Err(format!("unknown event type `{name}`")),
+    };
+    let has_data = matches!(
+        meta,
+        MetaMessage::Text(_)
+            | MetaMessage::Copyright(_)
+            | MetaMessage::TrackName(_)
+            | MetaMessage::InstrumentName(_)
+            | MetaMessage::Lyric(_)
+            | MetaMessage::Marker(_)
+            | MetaMessage::CuePoint(_)
+            | MetaMessage::ProgramName(_)
+            | MetaMessage::DeviceName(_)
+            | MetaMessage::SequencerSpecific(_)
+            | MetaMessage::Unknown(_, _)
+    );
+    Ok((TrackEventKind::Meta(meta), has_data))
+}
+
+/// Replaces the data of `kind` with `data`, if it carries any.
+fn with_data<'a>(kind: TrackEventKind<'static>, data: &'a [u8]) -> TrackEventKind<'a> {
+    match kind {
+        TrackEventKind::SysEx(_) => TrackEventKind::SysEx(data),
+        TrackEventKind::Escape(_) => TrackEventKind::Escape(data),
+        TrackEventKind::Meta(meta) => TrackEventKind::Meta(match meta {
+            MetaMessage::Text(_) => MetaMessage::Text(data),
+            MetaMessage::Copyright(_) => MetaMessage::Copyright(data),
+            MetaMessage::TrackName(_) => MetaMessage::TrackName(data),
+            MetaMessage::InstrumentName(_) => MetaMessage::InstrumentName(data),
+            MetaMessage::Lyric(_) => MetaMessage::Lyric(data),
+            MetaMessage::Marker(_) => MetaMessage::Marker(data),
+            MetaMessage::CuePoint(_) => MetaMessage::CuePoint(data),
+            MetaMessage::ProgramName(_) => MetaMessage::ProgramName(data),
+            MetaMessage::DeviceName(_) => MetaMessage::DeviceName(data),
+            MetaMessage::SequencerSpecific(_) => MetaMessage::SequencerSpecific(data),
+            MetaMessage::Unknown(meta_type, _) => MetaMessage::Unknown(meta_type, data),
+            meta => meta,
+        }),
+        kind => kind,
+    }
+}
+
+/// Parsed event line that owns its data.
+struct ParsedEvent {
+    delta: u28,
+    kind: TrackEventKind<'static>,
+    data: Vec<u8>,
+}
+
+fn parse_event(delta: &str, tokens: impl Iterator<Item = Token>) -> Result<ParsedEvent, String> {
+    let delta = delta
+        .strip_prefix('+')
+        .unwrap_or(delta)
+        .parse()
+        .ok()
+        .and_then(u28::try_from)
+        .ok_or_else(|| format!("invalid delta time `{delta}`"))?;
+    let mut tokens = tokens.peekable();
+    let name = match tokens.next() {
+        Some(Token::Word(name)) => name,
+        _ => return Err("missing event type".into()),
+    };
+    let mut args = Args::new(tokens)?;
+    let (kind, has_data) = parse_kind(&name, &mut args)?;
+    let data = args.finish(has_data)?;
+    Ok(ParsedEvent { delta, kind, data })
+}
+
+/// Parses `text` in the format described at the top of this module, and writes the resulting
+/// sequence as an SMF to stdout.
+pub fn assemble(text: &str) -> Result<(), Box<dyn Error>> {
+    assemble_to(text, io::stdout())
+}
+
+fn assemble_to(text: &str, out: impl io::Write) -> Result<(), Box<dyn Error>> {
+    let mut header = None;
+    let mut tracks: Vec<Vec<ParsedEvent>> = vec![];
+    for (line_i, line) in text.lines().enumerate() {
+        let with_line = |e: String| format!("line {}: {e}", line_i + 1);
+        let mut tokens = tokenize(line).map_err(with_line)?.into_iter();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        let word = match first {
+            Token::Word(word) => word,
+            _ => return Err(with_line("expected `smf`, `track`, or a delta time".into()).into()),
+        };
+        match word.as_str() {
+            "smf" if header.is_some() => {
+                return Err(with_line("duplicate `smf` line".into()).into())
+            }
+            "smf" => {
+                header = Some(
+                    Args::new(tokens)
+                        .and_then(parse_header)
+                        .map_err(with_line)?,
+                )
+            }
+            "track" if header.is_none() => {
+                return Err(with_line("`track` before the `smf` line".into()).into())
+            }
+            "track" => {
+                Args::new(tokens)
+                    .and_then(|args| args.finish(false))
+                    .map_err(with_line)?;
+                tracks.push(vec![]);
+            }
+            delta => {
+                let track = tracks
+                    .last_mut()
+                    .ok_or_else(|| with_line("event before the first `track` line".into()))?;
+                track.push(parse_event(delta, tokens).map_err(with_line)?);
+            }
+        }
+    }
+
+    let smf = Smf {
+        header: header.ok_or("missing `smf` line")?,
+        tracks: tracks
+            .iter()
+            .map(|track| {
+                track
+                    .iter()
+                    .map(|ev| TrackEvent {
+                        delta: ev.delta,
+                        kind: with_data(ev.kind, &ev.data),
+                    })
+                    .collect()
+            })
+            .collect(),
+    };
+    Ok(smf.write_std(out)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICAL: &str = r#"smf format=1 ppqn=480
+
+track # 0
++0 track_name "Conductor \"1\"\n"
++0 copyright "\xFF"
++0 tempo qn_micros=500000
++0 time_signature numerator=3 denominator_pow2=2 clocks_per_click=24 notes_32nd_per_qn=8
++0 key_signature sharps=-3 minor=true
++0 smpte_offset fps=29.97 hour=1 minute=2 second=3 frame=4 subframe=5
++0 marker "A"
++960 tempo qn_micros=250000
++0 end_of_track
+
+track # 1
++0 sys_ex 7E 7F 09 01 F7
++0 escape F3 01
++0 program_change channel=10 program=0
++0 controller channel=1 controller=7 value=100
++0 pitch_bend channel=2 bend=-8192
++120 note_on channel=1 key=60 vel=100
++0 aftertouch channel=1 key=60 vel=20
++0 channel_aftertouch channel=1 vel=30
++240 note_off channel=1 key=60 vel=64
++0 sequencer_specific 00 01
++0 unknown_meta meta_type=96 01 02
++0 midi_channel channel=3
++0 midi_port port=1
++0 lyric "la"
++0 end_of_track
+"#;
+
+    const TIMECODE: &str = "smf format=0 fps=29.97 subframes=80
+
+track # 0
++0 note_on channel=16 key=127 vel=1
++80 note_on channel=16 key=127 vel=0
++0 end_of_track
+";
+
+    fn assemble_bytes(text: &str) -> Vec<u8> {
+        let mut ret = vec![];
+        assemble_to(text, &mut ret).unwrap();
+        ret
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in [METRICAL, TIMECODE] {
+            let bytes = assemble_bytes(text);
+            let smf = Smf::parse(&bytes).unwrap();
+            assert_eq!(disassembly(&smf), text);
+            assert_eq!(assemble_bytes(&disassembly(&smf)), bytes);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_events() {
+        let header = "smf format=0 ppqn=96\ntrack\n";
+        for event in [
+            "+0 note_on channel=1 key=60 vel=100 00",
+            "+0 note_on channel=0 key=60 vel=100",
+            "+0 note_on channel=1 key=128 vel=100",
+            "+0 note_on channel=1 key=60",
+            "+0 note_on channel=1 key=60 vel=100 foo=1",
+            "+0 unknown_event",
+        ] {
+            let text = format!("{header}{event}\n");
+            assert!(assemble_to(&text, io::sink()).is_err(), "{event}");
+        }
+    }
+}
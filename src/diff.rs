@@ -0,0 +1,469 @@
+//! Event-level comparison of two sequences.
+
+use std::{cmp::max, error::Error, mem::discriminant};
+
+use midly::{Format as SmfFormat, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use serde::Serialize;
+
+use crate::{
+    asm,
+    dump::{Encoding, EventRecord, KindDisplay, SymbolicDisplay},
+    output::{self, Format},
+    time::{MidiTimeDisplay, TempoMap, TimeRecord},
+};
+
+/// A difference between two lists of timed items.
+enum Change<'a, T> {
+    Removed(u64, &'a T),
+    Inserted(u64, &'a T),
+    Changed(u64, &'a T, &'a T),
+}
+
+impl<T> Change<'_, T> {
+    fn pulse(&self) -> u64 {
+        match self {
+            Change::Removed(pulse, _)
+            | Change::Inserted(pulse, _)
+            | Change::Changed(pulse, _, _) => *pulse,
+        }
+    }
+}
+
+/// Compares two lists of items sorted by pulse. Items at the same pulse are matched regardless of
+/// their order. Any remaining items at the same pulse that `same_target` considers to describe the
+/// same thing are reported as changed, in the order they appear in `old` and `new`.
+fn diff_by_pulse<'a, T: PartialEq>(
+    old: &'a [(u64, T)],
+    new: &'a [(u64, T)],
+    same_target: impl Fn(&T, &T) -> bool,
+) -> Vec<Change<'a, T>> {
+    let mut ret = vec![];
+    let (mut old_i, mut new_i) = (0, 0);
+    while old_i < old.len() || new_i < new.len() {
+        let pulse = match (old.get(old_i), new.get(new_i)) {
+            (Some((a, _)), Some((b, _))) => *a.min(b),
+            (Some((pulse, _)), None) | (None, Some((pulse, _))) => *pulse,
+            (None, None) => unreachable!(),
+        };
+        let group = |items: &'a [(u64, T)], i: &mut usize| {
+            let start = *i;
+            while items.get(*i).is_some_and(|(p, _)| *p == pulse) {
+                *i += 1;
+            }
+            items[start..*i].iter().map(|(_, item)| item).collect()
+        };
+        let mut removed: Vec<&T> = group(old, &mut old_i);
+        let mut inserted: Vec<&T> = group(new, &mut new_i);
+
+        removed.retain(|a| match inserted.iter().position(|b| *a == *b) {
+            Some(b_i) => {
+                inserted.remove(b_i);
+                false
+            }
+            None => true,
+        });
+        for a in removed {
+            match inserted.iter().position(|b| same_target(a, b)) {
+                Some(b_i) => ret.push(Change::Changed(pulse, a, inserted.remove(b_i))),
+                None => ret.push(Change::Removed(pulse, a)),
+            }
+        }
+        ret.extend(inserted.into_iter().map(|b| Change::Inserted(pulse, b)));
+    }
+    ret
+}
+
+/// Returns whether two events affect the same parameter, i.e., they have the same type, channel,
+/// key, controller number, or meta event type.
+fn same_target(a: &TrackEventKind, b: &TrackEventKind) -> bool {
+    match (a, b) {
+        (
+            TrackEventKind::Midi {
+                channel: ch_a,
+                message: msg_a,
+            },
+            TrackEventKind::Midi {
+                channel: ch_b,
+                message: msg_b,
+            },
+        ) => {
+            (ch_a == ch_b)
+                && match (msg_a, msg_b) {
+                    (MidiMessage::NoteOff { key: a, .. }, MidiMessage::NoteOff { key: b, .. })
+                    | (MidiMessage::NoteOn { key: a, .. }, MidiMessage::NoteOn { key: b, .. })
+                    | (
+                        MidiMessage::Aftertouch { key: a, .. },
+                        MidiMessage::Aftertouch { key: b, .. },
+                    ) => a == b,
+                    (
+                        MidiMessage::Controller { controller: a, .. },
+                        MidiMessage::Controller { controller: b, .. },
+                    ) => a == b,
+                    _ => discriminant(msg_a) == discriminant(msg_b),
+                }
+        }
+        (TrackEventKind::Meta(a), TrackEventKind::Meta(b)) => discriminant(a) == discriminant(b),
+        _ => discriminant(a) == discriminant(b),
+    }
+}
+
+/// Returns all effective tempo changes of the sequence, skipping *Tempo* events that don't change
+/// the tempo.
+fn tempo_changes(smf: &Smf) -> Vec<(u64, u32)> {
+    let mut events = vec![];
+    for track in &smf.tracks {
+        let mut pulse = 0;
+        for ev in track {
+            pulse += ev.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = ev.kind {
+                events.push((pulse, tempo.as_int()));
+            }
+        }
+    }
+
+    // The sort is stable, which preserves the track order of simultaneous events.
+    events.sort_by_key(|(pulse, _)| *pulse);
+    let mut ret: Vec<(u64, u32)> = vec![];
+    for (pulse, tempo) in events {
+        match ret.last_mut() {
+            Some(last) if last.0 == pulse => last.1 = tempo,
+            Some(last) if last.1 == tempo => {}
+            _ => ret.push((pulse, tempo)),
+        }
+    }
+    ret
+}
+
+fn track_events<'a>(smf: &'a Smf, track_i: usize) -> Vec<(u64, TrackEventKind<'a>)> {
+    let mut pulse = 0;
+    smf.tracks.get(track_i).map_or(vec![], |track| {
+        track
+            .iter()
+            .map(|ev| {
+                pulse += ev.delta.as_int() as u64;
+                (pulse, ev.kind)
+            })
+            .collect()
+    })
+}
+
+fn format_name(format: SmfFormat) -> String {
+    match format {
+        SmfFormat::SingleTrack => "0",
+        SmfFormat::Parallel => "1",
+        SmfFormat::Sequential => "2",
+    }
+    .into()
+}
+
+fn timing_name(timing: Timing) -> String {
+    match timing {
+        Timing::Metrical(ppqn) => format!("ppqn={ppqn}"),
+        Timing::Timecode(fps, subframes) => {
+            format!("fps={} subframes={subframes}", asm::fps_name(fps))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HeaderRecord {
+    field: &'static str,
+    old: String,
+    new: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ChangeKind {
+    Removed,
+    Inserted,
+    Changed,
+}
+
+#[derive(Serialize)]
+struct ChangeRecord<T> {
+    change: ChangeKind,
+    time: TimeRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<T>,
+}
+
+#[derive(Serialize)]
+struct TempoRecord {
+    qn_micros: u32,
+    bpm: f64,
+}
+
+impl From<&u32> for TempoRecord {
+    fn from(qn_micros: &u32) -> Self {
+        TempoRecord {
+            qn_micros: *qn_micros,
+            bpm: 60_000_000.0 / (*qn_micros as f64),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrackRecord<'a> {
+    track: usize,
+    changes: Vec<ChangeRecord<EventRecord<'a>>>,
+}
+
+pub struct Options {
+    pub samplerate: Option<u32>,
+    pub realtime: bool,
+    pub symbolic: bool,
+    pub format: Format,
+}
+
+/// Prints the columns shared by all change lines.
+struct Columns<'a> {
+    time: MidiTimeDisplay<'a>,
+    realtime: bool,
+}
+
+const PULSE_HEADER: &str = "Pulse";
+const BAR_HEADER: &str = "Bar";
+const REALTIME_HEADER: &str = "Realtime";
+const SAMPLE_HEADER: &str = "Sample";
+
+impl Columns<'_> {
+    fn beat_header(&self) -> String {
+        let beat_unit = self.time.beat_unit();
+        format!("{}{}", beat_unit[..1].to_uppercase(), &beat_unit[1..])
+    }
+
+    /// Returns the widths of the pulse, beat, bar, realtime, and sample columns, which fit both
+    /// their headers and their values.
+    fn column_widths(&self) -> [usize; 5] {
+        let widths = self.time.widths();
+        [
+            max(PULSE_HEADER.chars().count(), widths.pulse),
+            max(self.beat_header().chars().count(), widths.beat),
+            max(BAR_HEADER.chars().count(), widths.bar),
+            max(REALTIME_HEADER.chars().count(), widths.realtime),
+            max(SAMPLE_HEADER.chars().count(), widths.sample),
+        ]
+    }
+
+    fn print_header(&self) {
+        let [pulse_width, beat_width, bar_width, realtime_width, sample_width] =
+            self.column_widths();
+        print!("{PULSE_HEADER:>pulse_width$}");
+        print!("\t{:>beat_width$}", self.beat_header());
+        if self.time.display_bar().is_some() {
+            print!("\t{BAR_HEADER:>bar_width$}");
+        }
+        if self.realtime {
+            print!("\t{REALTIME_HEADER:>realtime_width$}");
+        }
+        if self.time.display_sample().is_some() {
+            print!("\t{SAMPLE_HEADER:>sample_width$}");
+        }
+        println!("\t \tEvent");
+    }
+
+    fn print_line(&mut self, tempo_map: &TempoMap, pulse: u64, marker: char, desc: &str) {
+        self.time.time = tempo_map.time_at(pulse);
+        let [pulse_width, beat_width, bar_width, realtime_width, sample_width] =
+            self.column_widths();
+        print!("{:>pulse_width$}", self.time.display_pulse());
+        print!("\t{:>beat_width$}", self.time.display_beat());
+        if let Some(bar) = self.time.display_bar() {
+            print!("\t{bar:>bar_width$}");
+        }
+        if self.realtime {
+            print!("\t{:>realtime_width$}", self.time.display_realtime());
+        }
+        if let Some(sample) = self.time.display_sample() {
+            print!("\t{sample:>sample_width$}");
+        }
+        println!("\t{marker}\t{desc}");
+    }
+}
+
+fn print_header_changes(header: &[HeaderRecord]) {
+    for HeaderRecord { field, old, new } in header {
+        println!("{field}: {old} → {new}");
+    }
+}
+
+/// Compares `new` against `old`, and prints the differences in their headers, their effective
+/// tempo changes, and the events of every track.
+pub fn diff(old: &Smf, new: &Smf, opts: Options) -> Result<(), Box<dyn Error>> {
+    let mut header = vec![];
+    let mut header_field = |field, old: String, new: String| {
+        if old != new {
+            header.push(HeaderRecord { field, old, new });
+        }
+    };
+    header_field(
+        "format",
+        format_name(old.header.format),
+        format_name(new.header.format),
+    );
+    header_field(
+        "tracks",
+        old.tracks.len().to_string(),
+        new.tracks.len().to_string(),
+    );
+    header_field(
+        "timing",
+        timing_name(old.header.timing),
+        timing_name(new.header.timing),
+    );
+
+    // Pulses of different timings don't describe the same positions, so we can only report the
+    // header in that case.
+    if old.header.timing != new.header.timing {
+        if opts.format == Format::Json {
+            #[derive(Serialize)]
+            struct Output {
+                header: Vec<HeaderRecord>,
+            }
+            output::print_json(&Output { header })?;
+        } else {
+            println!("## Header\n");
+            print_header_changes(&header);
+        }
+        return Err(
+            "events of sequences with different timings can't be compared by pulse; use \
+            `equiv` to check whether they play identically"
+                .into(),
+        );
+    }
+
+    let tempo_map = TempoMap::new(old);
+    let end = max(
+        tempo_map.time_at_end(old).pulse(),
+        TempoMap::new(new).time_at_end(new).pulse(),
+    );
+    let mut columns = Columns {
+        time: MidiTimeDisplay::new_at(&tempo_map, end, opts.samplerate),
+        realtime: opts.realtime,
+    };
+
+    let (tempo_old, tempo_new) = (tempo_changes(old), tempo_changes(new));
+    let tempo = diff_by_pulse(&tempo_old, &tempo_new, |_, _| true);
+    let track_events = (0..max(old.tracks.len(), new.tracks.len()))
+        .map(|track_i| (track_events(old, track_i), track_events(new, track_i)))
+        .collect::<Vec<_>>();
+    let tracks = track_events
+        .iter()
+        .map(|(old, new)| diff_by_pulse(old, new, same_target))
+        .collect::<Vec<_>>();
+
+    if opts.format == Format::Json {
+        fn records<'a, T, R: From<&'a T>>(
+            columns: &mut Columns,
+            tempo_map: &TempoMap,
+            changes: &[Change<'a, T>],
+        ) -> Vec<ChangeRecord<R>> {
+            changes
+                .iter()
+                .map(|change| {
+                    columns.time.time = tempo_map.time_at(change.pulse());
+                    let time = columns.time.record();
+                    let (change, old, new) = match change {
+                        Change::Removed(_, old) => (ChangeKind::Removed, Some(*old), None),
+                        Change::Inserted(_, new) => (ChangeKind::Inserted, None, Some(*new)),
+                        Change::Changed(_, old, new) => {
+                            (ChangeKind::Changed, Some(*old), Some(*new))
+                        }
+                    };
+                    ChangeRecord {
+                        change,
+                        time,
+                        old: old.map(R::from),
+                        new: new.map(R::from),
+                    }
+                })
+                .collect()
+        }
+
+        let tempo = records::<_, TempoRecord>(&mut columns, &tempo_map, &tempo);
+        let tracks = tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, changes)| !changes.is_empty())
+            .map(|(track, changes)| TrackRecord {
+                track,
+                changes: records(&mut columns, &tempo_map, changes),
+            })
+            .collect();
+
+        #[derive(Serialize)]
+        struct Output<'a> {
+            header: Vec<HeaderRecord>,
+            tempo: Vec<ChangeRecord<TempoRecord>>,
+            tracks: Vec<TrackRecord<'a>>,
+        }
+        return Ok(output::print_json(&Output {
+            header,
+            tempo,
+            tracks,
+        })?);
+    }
+
+    let mut first = true;
+    let mut section = |title: &str| {
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("## {title}\n");
+    };
+    if !header.is_empty() {
+        section("Header");
+        print_header_changes(&header);
+    }
+
+    if !tempo.is_empty() {
+        section("Tempo");
+        columns.print_header();
+        let desc = |qn_micros: &u32| {
+            let TempoRecord { qn_micros, bpm } = TempoRecord::from(qn_micros);
+            format!("{qn_micros} µs per quarter note ({bpm:.3} BPM)")
+        };
+        for change in &tempo {
+            let pulse = change.pulse();
+            match change {
+                Change::Removed(_, old) => columns.print_line(&tempo_map, pulse, '-', &desc(old)),
+                Change::Inserted(_, new) => columns.print_line(&tempo_map, pulse, '+', &desc(new)),
+                Change::Changed(_, old, new) => {
+                    let desc = format!("{} → {}", desc(old), desc(new));
+                    columns.print_line(&tempo_map, pulse, '~', &desc)
+                }
+            }
+        }
+    }
+
+    for (track_i, changes) in tracks.iter().enumerate() {
+        if changes.is_empty() {
+            continue;
+        }
+        section(&format!("Track {track_i}"));
+        columns.print_header();
+        let desc = |kind: &TrackEventKind| {
+            if opts.symbolic {
+                SymbolicDisplay(kind, Encoding::Auto).to_string()
+            } else {
+                KindDisplay(kind, Encoding::Auto).to_string()
+            }
+        };
+        for change in changes {
+            let pulse = change.pulse();
+            match change {
+                Change::Removed(_, old) => columns.print_line(&tempo_map, pulse, '-', &desc(old)),
+                Change::Inserted(_, new) => columns.print_line(&tempo_map, pulse, '+', &desc(new)),
+                Change::Changed(_, old, new) => {
+                    let desc = format!("{} → {}", desc(old), desc(new));
+                    columns.print_line(&tempo_map, pulse, '~', &desc)
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,17 @@
 //! MIDI event dumping.
 
-use std::{cmp::max, fmt::Debug};
+use std::{borrow::Cow, cmp::max, error::Error, fmt::Debug};
 
-use midly::{MetaMessage, Smf, TrackEventKind};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use serde::Serialize;
 
-use crate::time::MidiTimeDisplay;
+use crate::{
+    event::{self, Category},
+    names,
+    output::{self, Format},
+    sysex,
+    time::{self, MidiTimeDisplay, TempoMap, TimeRecord},
+};
 
 struct Hex<'a>(&'a [u8]);
 
@@ -17,23 +24,84 @@ impl<'a> std::fmt::Display for Hex<'a> {
     }
 }
 
+/// Character encoding of meta text events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// UTF-8 if valid, Shift-JIS otherwise
+    #[default]
+    Auto,
+
+    /// Unicode
+    #[value(name = "utf-8")]
+    Utf8,
+
+    /// Japanese, with the Microsoft extensions of code page 932
+    #[value(name = "shift-jis")]
+    ShiftJis,
+
+    /// ISO 8859-1, which never fails to decode
+    #[value(name = "latin-1")]
+    Latin1,
+}
+
+impl Encoding {
+    /// Decodes `bytes`, returning `None` if they are invalid in this encoding.
+    fn decode(self, bytes: &[u8]) -> Option<Cow<'_, str>> {
+        match self {
+            Encoding::Auto => Encoding::Utf8
+                .decode(bytes)
+                .or_else(|| Encoding::ShiftJis.decode(bytes)),
+            Encoding::Utf8 => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            Encoding::ShiftJis => {
+                encoding_rs::SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes)
+            }
+            Encoding::Latin1 => Some(Cow::Owned(bytes.iter().map(|&b| b as char).collect())),
+        }
+    }
+}
+
+/// Shows text in the given encoding as a quoted string, or as hex bytes if it's invalid.
+struct TextDisplay<'a>(&'a [u8], Encoding);
+
+impl<'a> std::fmt::Display for TextDisplay<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1.decode(self.0) {
+            Some(text) => write!(f, "{text:?}"),
+            None => write!(f, "{}", Hex(self.0)),
+        }
+    }
+}
+
 #[derive(Debug)]
-struct KindDisplay<'a>(&'a TrackEventKind<'a>);
+pub struct KindDisplay<'a>(pub &'a TrackEventKind<'a>, pub Encoding);
 
 impl<'a> std::fmt::Display for KindDisplay<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enc = self.1;
         match self.0 {
-            TrackEventKind::SysEx(b) => write!(f, "SysEx({})", Hex(b)),
+            TrackEventKind::SysEx(b) => {
+                write!(f, "SysEx({})", Hex(b))?;
+                match sysex::describe(b) {
+                    Some(desc) => write!(f, " → {desc}"),
+                    None => Ok(()),
+                }
+            }
             TrackEventKind::Meta(meta) => match meta {
-                MetaMessage::Text(b) => write!(f, "Meta(Text({}))", Hex(b)),
-                MetaMessage::Copyright(b) => write!(f, "Meta(Copyright({}))", Hex(b)),
-                MetaMessage::TrackName(b) => write!(f, "Meta(TrackName({}))", Hex(b)),
-                MetaMessage::Marker(b) => write!(f, "Meta(Marker({}))", Hex(b)),
-                MetaMessage::InstrumentName(b) => write!(f, "Meta(InstrumentName({}))", Hex(b)),
-                MetaMessage::Lyric(b) => write!(f, "Meta(Lyric({}))", Hex(b)),
-                MetaMessage::CuePoint(b) => write!(f, "Meta(CuePoint({}))", Hex(b)),
-                MetaMessage::ProgramName(b) => write!(f, "Meta(ProgramName({}))", Hex(b)),
-                MetaMessage::DeviceName(b) => write!(f, "Meta(DeviceName({}))", Hex(b)),
+                MetaMessage::Text(b) => write!(f, "Meta(Text({}))", TextDisplay(b, enc)),
+                MetaMessage::Copyright(b) => write!(f, "Meta(Copyright({}))", TextDisplay(b, enc)),
+                MetaMessage::TrackName(b) => write!(f, "Meta(TrackName({}))", TextDisplay(b, enc)),
+                MetaMessage::Marker(b) => write!(f, "Meta(Marker({}))", TextDisplay(b, enc)),
+                MetaMessage::InstrumentName(b) => {
+                    write!(f, "Meta(InstrumentName({}))", TextDisplay(b, enc))
+                }
+                MetaMessage::Lyric(b) => write!(f, "Meta(Lyric({}))", TextDisplay(b, enc)),
+                MetaMessage::CuePoint(b) => write!(f, "Meta(CuePoint({}))", TextDisplay(b, enc)),
+                MetaMessage::ProgramName(b) => {
+                    write!(f, "Meta(ProgramName({}))", TextDisplay(b, enc))
+                }
+                MetaMessage::DeviceName(b) => {
+                    write!(f, "Meta(DeviceName({}))", TextDisplay(b, enc))
+                }
                 MetaMessage::SequencerSpecific(b) => {
                     write!(f, "Meta(SequencerSpecific({}))", Hex(b))
                 }
@@ -44,34 +112,426 @@ impl<'a> std::fmt::Display for KindDisplay<'a> {
     }
 }
 
-pub fn dump(smf: &Smf) {
+/// Shows channel messages with 1-based channels and symbolic names for notes, programs, and
+/// controllers, and all other events like `KindDisplay`.
+pub struct SymbolicDisplay<'a>(pub &'a TrackEventKind<'a>, pub Encoding);
+
+impl<'a> std::fmt::Display for SymbolicDisplay<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let TrackEventKind::Midi { channel, message } = *self.0 else {
+            return write!(f, "{}", KindDisplay(self.0, self.1));
+        };
+        let is_drum = channel.as_int() == names::GM_PERCUSSION_CHANNEL;
+        let key_name = |key| names::key(key, channel);
+        write!(f, "ch{} ", channel.as_int() + 1)?;
+        match message {
+            MidiMessage::NoteOff { key, vel } => write!(f, "NoteOff {} vel={vel}", key_name(key)),
+            MidiMessage::NoteOn { key, vel } => write!(f, "NoteOn {} vel={vel}", key_name(key)),
+            MidiMessage::Aftertouch { key, vel } => {
+                write!(f, "Aftertouch {} vel={vel}", key_name(key))
+            }
+            MidiMessage::Controller { controller, value } => {
+                write!(f, "CC{controller}")?;
+                if let Some(name) = names::controller(controller) {
+                    write!(f, " {name}")?;
+                }
+                write!(f, " = {value}")
+            }
+            MidiMessage::ProgramChange { program } => {
+                let name = if is_drum {
+                    names::gs_drum_kit(program)
+                } else {
+                    Some(names::gm_program(program))
+                };
+                write!(f, "Program {program}")?;
+                match name {
+                    Some(name) => write!(f, " ({name})"),
+                    None => Ok(()),
+                }
+            }
+            MidiMessage::ChannelAftertouch { vel } => write!(f, "ChannelAftertouch vel={vel}"),
+            MidiMessage::PitchBend { bend } => write!(f, "PitchBend {:+}", bend.as_int()),
+        }
+    }
+}
+
+/// Machine-readable representation of a `TrackEventKind`. Channels are 1-based, and pitch bend
+/// values are signed and centered around 0.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventRecord<'a> {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        vel: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        vel: u8,
+    },
+    Aftertouch {
+        channel: u8,
+        key: u8,
+        vel: u8,
+    },
+    Controller {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelAftertouch {
+        channel: u8,
+        vel: u8,
+    },
+    PitchBend {
+        channel: u8,
+        bend: i16,
+    },
+    SysEx {
+        data: &'a [u8],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+    Escape {
+        data: &'a [u8],
+    },
+    TrackNumber {
+        number: Option<u16>,
+    },
+    Text {
+        data: &'a [u8],
+    },
+    Copyright {
+        data: &'a [u8],
+    },
+    TrackName {
+        data: &'a [u8],
+    },
+    InstrumentName {
+        data: &'a [u8],
+    },
+    Lyric {
+        data: &'a [u8],
+    },
+    Marker {
+        data: &'a [u8],
+    },
+    CuePoint {
+        data: &'a [u8],
+    },
+    ProgramName {
+        data: &'a [u8],
+    },
+    DeviceName {
+        data: &'a [u8],
+    },
+    MidiChannel {
+        channel: u8,
+    },
+    MidiPort {
+        port: u8,
+    },
+    EndOfTrack,
+    Tempo {
+        qn_micros: u32,
+    },
+    SmpteOffset {
+        fps: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        frame: u8,
+        subframe: u8,
+    },
+    TimeSignature {
+        numerator: u8,
+        denominator_pow2: u8,
+        clocks_per_click: u8,
+        notes_32nd_per_qn: u8,
+    },
+    KeySignature {
+        sharps: i8,
+        minor: bool,
+    },
+    SequencerSpecific {
+        data: &'a [u8],
+    },
+    UnknownMeta {
+        meta_type: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> From<&TrackEventKind<'a>> for EventRecord<'a> {
+    fn from(kind: &TrackEventKind<'a>) -> Self {
+        match *kind {
+            TrackEventKind::Midi { channel, message } => {
+                let channel = channel.as_int() + 1;
+                match message {
+                    MidiMessage::NoteOff { key, vel } => EventRecord::NoteOff {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                    },
+                    MidiMessage::NoteOn { key, vel } => EventRecord::NoteOn {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                    },
+                    MidiMessage::Aftertouch { key, vel } => EventRecord::Aftertouch {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                    },
+                    MidiMessage::Controller { controller, value } => EventRecord::Controller {
+                        channel,
+                        controller: controller.as_int(),
+                        value: value.as_int(),
+                    },
+                    MidiMessage::ProgramChange { program } => EventRecord::ProgramChange {
+                        channel,
+                        program: program.as_int(),
+                    },
+                    MidiMessage::ChannelAftertouch { vel } => EventRecord::ChannelAftertouch {
+                        channel,
+                        vel: vel.as_int(),
+                    },
+                    MidiMessage::PitchBend { bend } => EventRecord::PitchBend {
+                        channel,
+                        bend: bend.as_int(),
+                    },
+                }
+            }
+            TrackEventKind::SysEx(data) => EventRecord::SysEx {
+                data,
+                description: sysex::describe(data),
+            },
+            TrackEventKind::Escape(data) => EventRecord::Escape { data },
+            TrackEventKind::Meta(meta) => match meta {
+                MetaMessage::TrackNumber(number) => EventRecord::TrackNumber { number },
+                MetaMessage::Text(data) => EventRecord::Text { data },
+                MetaMessage::Copyright(data) => EventRecord::Copyright { data },
+                MetaMessage::TrackName(data) => EventRecord::TrackName { data },
+                MetaMessage::InstrumentName(data) => EventRecord::InstrumentName { data },
+                MetaMessage::Lyric(data) => EventRecord::Lyric { data },
+                MetaMessage::Marker(data) => EventRecord::Marker { data },
+                MetaMessage::CuePoint(data) => EventRecord::CuePoint { data },
+                MetaMessage::ProgramName(data) => EventRecord::ProgramName { data },
+                MetaMessage::DeviceName(data) => EventRecord::DeviceName { data },
+                MetaMessage::MidiChannel(channel) => EventRecord::MidiChannel {
+                    channel: channel.as_int() + 1,
+                },
+                MetaMessage::MidiPort(port) => EventRecord::MidiPort {
+                    port: port.as_int(),
+                },
+                MetaMessage::EndOfTrack => EventRecord::EndOfTrack,
+                MetaMessage::Tempo(tempo) => EventRecord::Tempo {
+                    qn_micros: tempo.as_int(),
+                },
+                MetaMessage::SmpteOffset(smpte) => EventRecord::SmpteOffset {
+                    fps: smpte.fps().as_int(),
+                    hour: smpte.hour(),
+                    minute: smpte.minute(),
+                    second: smpte.second(),
+                    frame: smpte.frame(),
+                    subframe: smpte.subframe(),
+                },
+                MetaMessage::TimeSignature(
+                    numerator,
+                    denominator_pow2,
+                    clocks_per_click,
+                    notes_32nd_per_qn,
+                ) => EventRecord::TimeSignature {
+                    numerator,
+                    denominator_pow2,
+                    clocks_per_click,
+                    notes_32nd_per_qn,
+                },
+                MetaMessage::KeySignature(sharps, minor) => {
+                    EventRecord::KeySignature { sharps, minor }
+                }
+                MetaMessage::SequencerSpecific(data) => EventRecord::SequencerSpecific { data },
+                MetaMessage::Unknown(meta_type, data) => {
+                    EventRecord::UnknownMeta { meta_type, data }
+                }
+            },
+        }
+    }
+}
+
+/// Selects the events shown by `dump()`.
+pub struct Filter {
+    /// 0-based track numbers. Empty to show all tracks.
+    pub tracks: Vec<usize>,
+
+    /// 1-based MIDI channels. Empty to show events on all channels as well as events without a
+    /// channel.
+    pub channels: Vec<u8>,
+
+    /// Empty to show all categories.
+    pub categories: Vec<Category>,
+
+    /// Pulse range, with the end being exclusive if given.
+    pub range: (u64, Option<u64>),
+}
+
+impl Filter {
+    fn shows_track(&self, track_i: usize) -> bool {
+        self.tracks.is_empty() || self.tracks.contains(&track_i)
+    }
+
+    fn shows(&self, pulse: u64, kind: &TrackEventKind) -> bool {
+        let (start, end) = self.range;
+        (pulse >= start)
+            && end.is_none_or(|end| pulse < end)
+            && (self.channels.is_empty()
+                || event::channel(kind)
+                    .is_some_and(|ch| self.channels.contains(&(ch.as_int() + 1))))
+            && (self.categories.is_empty() || self.categories.contains(&Category::of(kind)))
+    }
+}
+
+pub struct Options {
+    pub samplerate: Option<u32>,
+    pub realtime: bool,
+    pub symbolic: bool,
+    pub format: Format,
+    pub encoding: Encoding,
+    pub filter: Filter,
+}
+
+#[derive(Serialize)]
+struct DumpEvent<'a> {
+    index: usize,
+    delta: u32,
+    time: TimeRecord,
+    #[serde(flatten)]
+    kind: EventRecord<'a>,
+}
+
+#[derive(Serialize)]
+struct DumpTrack<'a> {
+    track: usize,
+    events: Vec<DumpEvent<'a>>,
+}
+
+fn dump_json(smf: &Smf, tempo_map: &TempoMap, opts: &Options) -> Result<(), Box<dyn Error>> {
+    let tracks = smf
+        .tracks
+        .iter()
+        .enumerate()
+        .filter(|(track_i, _)| opts.filter.shows_track(*track_i))
+        .map(|(track_i, track)| {
+            let mut time = MidiTimeDisplay::new(tempo_map, track, opts.samplerate);
+            let events = track
+                .iter()
+                .enumerate()
+                .filter_map(|(ev_i, ev)| {
+                    time.advance(ev);
+                    opts.filter
+                        .shows(time.time.pulse(), &ev.kind)
+                        .then(|| DumpEvent {
+                            index: ev_i,
+                            delta: ev.delta.as_int(),
+                            time: time.record(),
+                            kind: EventRecord::from(&ev.kind),
+                        })
+                })
+                .collect();
+            DumpTrack {
+                track: track_i,
+                events,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    #[derive(Serialize)]
+    struct Output<'a> {
+        tracks: Vec<DumpTrack<'a>>,
+    }
+    Ok(output::print_json(&Output { tracks })?)
+}
+
+pub fn dump(smf: &Smf, tempo_map: &TempoMap, opts: Options) -> Result<(), Box<dyn Error>> {
+    time::validate_pulse_range(smf, opts.filter.range)?;
+    if opts.format == Format::Json {
+        return dump_json(smf, tempo_map, &opts);
+    }
+
     let delta_header = "Delta";
     let pulse_header = "Pulse";
-    let beat_header = "Beat";
+    let beat_header = match smf.header.timing {
+        Timing::Metrical(_) => "Beat",
+        Timing::Timecode(_, _) => "Timecode",
+    };
+    let bar_header = "Bar";
+    let realtime_header = "Realtime";
+    let sample_header = "Sample";
 
+    let mut first = true;
     for (track_i, track) in smf.tracks.iter().enumerate() {
-        let mut time = MidiTimeDisplay::new(&smf.header.timing, track, None);
+        if !opts.filter.shows_track(track_i) {
+            continue;
+        }
+        let mut time = MidiTimeDisplay::new(tempo_map, track, opts.samplerate);
         let widths = time.widths();
         let delta_width = max(delta_header.chars().count(), widths.delta);
         let pulse_width = max(pulse_header.chars().count(), widths.pulse);
         let beat_width = max(beat_header.chars().count(), widths.beat);
-        if track_i != 0 {
+        let bar_width = max(bar_header.chars().count(), widths.bar);
+        let realtime_width = max(realtime_header.chars().count(), widths.realtime);
+        let sample_width = max(sample_header.chars().count(), widths.sample);
+        let has_bars = time.display_bar().is_some();
+        if !first {
             println!();
         }
+        first = false;
         println!("## Track {track_i}\n");
-        println!(
-            "{:>delta_width$}\t{:>pulse_width$}\t{:>beat_width$}\tEvent",
+        print!(
+            "{:>delta_width$}\t{:>pulse_width$}\t{:>beat_width$}",
             delta_header, pulse_header, beat_header,
         );
+        if has_bars {
+            print!("\t{bar_header:>bar_width$}");
+        }
+        if opts.realtime {
+            print!("\t{realtime_header:>realtime_width$}");
+        }
+        if opts.samplerate.is_some() {
+            print!("\t{sample_header:>sample_width$}");
+        }
+        println!("\tEvent");
         for ev in track {
-            time.time = time.time + ev;
-            println!(
-                "{:>+delta_width$}\t{:>pulse_width$}\t{:>beat_width$}\t{:}",
+            time.advance(ev);
+            if !opts.filter.shows(time.time.pulse(), &ev.kind) {
+                continue;
+            }
+            print!(
+                "{:>+delta_width$}\t{:>pulse_width$}\t{:>beat_width$}",
                 ev.delta,
                 time.display_pulse(),
                 time.display_beat(),
-                KindDisplay(&ev.kind),
             );
+            if let Some(bar) = time.display_bar() {
+                print!("\t{bar:>bar_width$}");
+            }
+            if opts.realtime {
+                print!("\t{:>realtime_width$}", time.display_realtime());
+            }
+            if let Some(sample) = time.display_sample() {
+                print!("\t{sample:>sample_width$}");
+            }
+            if opts.symbolic {
+                println!("\t{}", SymbolicDisplay(&ev.kind, opts.encoding));
+            } else {
+                println!("\t{}", KindDisplay(&ev.kind, opts.encoding));
+            }
         }
     }
+    Ok(())
 }
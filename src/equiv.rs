@@ -0,0 +1,271 @@
+//! Musical equivalence of two sequences.
+
+use std::error::Error;
+
+use midly::{num::u24, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use serde::Serialize;
+
+use crate::{
+    dump::{Encoding, EventRecord, SymbolicDisplay},
+    event,
+    output::{self, Format},
+    smf,
+    state::MidiState,
+    time::{MidiTimeDisplay, Realtime, TempoMap, TimeRecord},
+};
+
+/// Comparable time of a normalized event.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Time {
+    /// Pulse, scaled to a common PPQN value for both sequences.
+    Pulse(u64),
+    Realtime(Realtime),
+}
+
+/// An audible event, normalized for comparison.
+struct Normalized<'a> {
+    time: Time,
+    pulse: u64,
+    track: usize,
+    index: usize,
+    kind: TrackEventKind<'a>,
+
+    /// The event as it appears in the sequence.
+    original: TrackEventKind<'a>,
+}
+
+/// Order-relevant class of a channel message within the same point in time.
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    /// Notes for different keys commute.
+    Note,
+
+    /// Controller changes for different controllers commute.
+    Controller,
+
+    /// Commutes with nothing on the same channel.
+    Other,
+}
+
+/// Returns the audible events of the sequence in a canonical order. Note Off events and *Note On*
+/// events with zero velocity are both turned into *Note Off* events with zero velocity, and
+/// controller and pitch bend changes that don't change the state of their channel are removed.
+/// Meta events are removed, with the exception of tempo changes if `tempo` is true.
+fn normalize<'a>(smf: &'a Smf, time_of: impl Fn(u64) -> Time, tempo: bool) -> Vec<Normalized<'a>> {
+    let mut state = MidiState::new();
+
+    // Sequences start at 120 BPM until their first tempo change.
+    let mut qn_micros_cur = u24::from(500_000);
+    let mut events = vec![];
+    for merged in smf::merge(smf) {
+        let ev = merged.ev;
+        let kind = match ev.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let ch = &state.ch[channel.as_int() as usize];
+                let message = match message {
+                    MidiMessage::NoteOn { key, vel } if vel == 0 => {
+                        MidiMessage::NoteOff { key, vel: 0.into() }
+                    }
+                    MidiMessage::NoteOff { key, .. } => MidiMessage::NoteOff { key, vel: 0.into() },
+                    MidiMessage::Controller { controller, value }
+                        if !event::is_controller_action(controller)
+                            && ch.cc[controller.as_int() as usize] == value =>
+                    {
+                        continue;
+                    }
+                    MidiMessage::PitchBend { bend } if ch.bend == bend => continue,
+                    message => message,
+                };
+                TrackEventKind::Midi { channel, message }
+            }
+            TrackEventKind::SysEx(_) | TrackEventKind::Escape(_) => ev.kind,
+            TrackEventKind::Meta(MetaMessage::Tempo(qn_micros))
+                if tempo && (qn_micros != qn_micros_cur) =>
+            {
+                qn_micros_cur = qn_micros;
+                ev.kind
+            }
+            TrackEventKind::Meta(_) => continue,
+        };
+        state.update(ev);
+        events.push(Normalized {
+            time: time_of(merged.pulse),
+            pulse: merged.pulse,
+            track: merged.track,
+            index: merged.index,
+            kind,
+            original: ev.kind,
+        });
+    }
+
+    // Bring simultaneous events into a canonical order. SysEx events commute with nothing. In
+    // between, events on different channels commute, as do runs of notes for different keys and
+    // runs of controller changes for different controllers on the same channel. Tempo changes
+    // only commute with channel messages.
+    let mut keys = Vec::with_capacity(events.len());
+    let mut group_time = None;
+    let mut segment = 0;
+    let mut runs = [(0, Class::Other); 17];
+    for ev in &events {
+        if group_time != Some(ev.time) {
+            group_time = Some(ev.time);
+            segment += 1;
+            runs = [(0, Class::Other); 17];
+        }
+        let (slot, class, sub) = match ev.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let (class, sub) = match message {
+                    MidiMessage::NoteOff { key, .. } | MidiMessage::NoteOn { key, .. } => {
+                        (Class::Note, key.as_int())
+                    }
+                    MidiMessage::Controller { controller, .. }
+                        if !event::is_controller_action(controller) =>
+                    {
+                        (Class::Controller, controller.as_int())
+                    }
+                    _ => (Class::Other, 0),
+                };
+                (channel.as_int() as usize, class, sub)
+            }
+            TrackEventKind::Meta(_) => (16, Class::Other, 0),
+            TrackEventKind::SysEx(_) | TrackEventKind::Escape(_) => {
+                segment += 1;
+                keys.push((segment, 0, 0, 0));
+                segment += 1;
+                runs = [(0, Class::Other); 17];
+                continue;
+            }
+        };
+        let run = &mut runs[slot];
+        if class == Class::Other || class != run.1 {
+            run.0 += 1;
+            run.1 = class;
+        }
+        keys.push((segment, slot, run.0, sub));
+    }
+    let mut keyed = keys.into_iter().zip(events).collect::<Vec<_>>();
+
+    // The sort is stable, which preserves the order of events that don't commute.
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed.into_iter().map(|(_, ev)| ev).collect()
+}
+
+#[derive(Serialize)]
+struct EventSide<'a> {
+    time: TimeRecord,
+    track: usize,
+    event: usize,
+    #[serde(flatten)]
+    kind: EventRecord<'a>,
+}
+
+pub struct Options {
+    pub samplerate: Option<u32>,
+    pub realtime: bool,
+    pub format: Format,
+}
+
+/// Checks whether `new` plays identically to `old`, and prints the first audible difference if it
+/// doesn't.
+pub fn equiv(old: &Smf, new: &Smf, opts: Options) -> Result<(), Box<dyn Error>> {
+    let tempo_old = TempoMap::new(old);
+    let tempo_new = TempoMap::new(new);
+
+    let (old_events, new_events) = if opts.realtime {
+        let realtime_of = |tempo_map: &TempoMap, pulse| tempo_map.time_at(pulse).realtime();
+        (
+            normalize(old, |p| Time::Realtime(realtime_of(&tempo_old, p)), false),
+            normalize(new, |p| Time::Realtime(realtime_of(&tempo_new, p)), false),
+        )
+    } else {
+        let (scale_old, scale_new) = match (old.header.timing, new.header.timing) {
+            (Timing::Metrical(ppqn_old), Timing::Metrical(ppqn_new)) => {
+                let (ppqn_old, ppqn_new) = (ppqn_old.as_int() as u64, ppqn_new.as_int() as u64);
+                let (mut a, mut b) = (ppqn_old, ppqn_new);
+                while b != 0 {
+                    (a, b) = (b, a % b);
+                }
+                (ppqn_new / a, ppqn_old / a)
+            }
+            (timing_old, timing_new) if timing_old == timing_new => (1, 1),
+            _ => {
+                return Err(
+                    "sequences with different SMPTE timings can only be compared in realtime \
+                    (`-R`/`--realtime`)"
+                        .into(),
+                )
+            }
+        };
+        (
+            normalize(old, |p| Time::Pulse(p * scale_old), true),
+            normalize(new, |p| Time::Pulse(p * scale_new), true),
+        )
+    };
+
+    let difference = (0..old_events.len().max(new_events.len())).find_map(|i| {
+        let (a, b) = (old_events.get(i), new_events.get(i));
+        match (a, b) {
+            (Some(a), Some(b)) if (a.time == b.time) && (a.kind == b.kind) => None,
+            _ => Some((a, b)),
+        }
+    });
+    let Some((ev_old, ev_new)) = difference else {
+        if opts.format == Format::Json {
+            #[derive(Serialize)]
+            struct Output {
+                equivalent: bool,
+            }
+            output::print_json(&Output { equivalent: true })?;
+        }
+        return Ok(());
+    };
+
+    let old_side = ev_old.map(|ev| {
+        (
+            ev,
+            MidiTimeDisplay::new_at(&tempo_old, ev.pulse, opts.samplerate),
+        )
+    });
+    let new_side = ev_new.map(|ev| {
+        (
+            ev,
+            MidiTimeDisplay::new_at(&tempo_new, ev.pulse, opts.samplerate),
+        )
+    });
+    if opts.format == Format::Json {
+        fn record<'a>(side: &Option<(&Normalized<'a>, MidiTimeDisplay)>) -> Option<EventSide<'a>> {
+            side.as_ref().map(|(ev, time)| EventSide {
+                time: time.record(),
+                track: ev.track,
+                event: ev.index,
+                kind: EventRecord::from(&ev.original),
+            })
+        }
+
+        #[derive(Serialize)]
+        struct Output<'a> {
+            equivalent: bool,
+            old: Option<EventSide<'a>>,
+            new: Option<EventSide<'a>>,
+        }
+        output::print_json(&Output {
+            equivalent: false,
+            old: record(&old_side),
+            new: record(&new_side),
+        })?;
+    } else {
+        println!("First difference:");
+        for (marker, side) in [('-', old_side), ('+', new_side)] {
+            match side {
+                Some((ev, time)) => println!(
+                    "{marker} {time}: {} (track {}, event {})",
+                    SymbolicDisplay(&ev.original, Encoding::Auto),
+                    ev.track,
+                    ev.index
+                ),
+                None => println!("{marker} (no further events)"),
+            }
+        }
+    }
+    Err("the sequences are not equivalent".into())
+}
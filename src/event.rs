@@ -1,10 +1,14 @@
 //! Helpful event inspection wrappers.
 
+use std::{ops::RangeInclusive, str::FromStr};
+
 use midly::{
     num::{u4, u7},
     MidiMessage, TrackEvent, TrackEventKind,
 };
 
+use crate::names;
+
 pub struct Note {
     pub channel: u4,
     pub key: u7,
@@ -32,6 +36,19 @@ pub fn note_on(ev: &TrackEvent) -> Option<Note> {
     note(ev).filter(|n| n.is_on())
 }
 
+/// Matches both *Note Off* events and *Note On* events with zero velocity. For the former, `vel`
+/// contains the release velocity.
+pub fn note_off(ev: &TrackEvent) -> Option<Note> {
+    if let TrackEventKind::Midi {
+        channel,
+        message: MidiMessage::NoteOff { key, vel },
+    } = ev.kind
+    {
+        return Some(Note { channel, key, vel });
+    }
+    note(ev).filter(|n| !n.is_on())
+}
+
 pub struct Controller {
     pub channel: u4,
     pub controller: u7,
@@ -53,3 +70,100 @@ pub fn controller(ev: &TrackEvent) -> Option<Controller> {
     }
     None
 }
+
+/// Returns whether the given controller triggers an action or depends on the order of other
+/// controllers, rather than simply setting a value. This applies to data entry, RPN and NRPN
+/// selection, and channel mode messages.
+pub fn is_controller_action(controller: u7) -> bool {
+    matches!(controller.as_int(), 6 | 38 | 96..=101 | 120..=127)
+}
+
+/// Broad event categories for filtering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Category {
+    /// *Note On* and *Note Off* events
+    Note,
+
+    /// *Control Change* events
+    Cc,
+
+    /// *Program Change* events
+    Program,
+
+    /// *Pitch Bend* events
+    Bend,
+
+    /// Polyphonic and channel aftertouch events
+    Aftertouch,
+
+    /// System Exclusive and escape events
+    Sysex,
+
+    /// Meta events
+    Meta,
+}
+
+impl Category {
+    pub fn of(kind: &TrackEventKind) -> Self {
+        match kind {
+            TrackEventKind::Midi { message, .. } => match message {
+                MidiMessage::NoteOff { .. } | MidiMessage::NoteOn { .. } => Category::Note,
+                MidiMessage::Controller { .. } => Category::Cc,
+                MidiMessage::ProgramChange { .. } => Category::Program,
+                MidiMessage::PitchBend { .. } => Category::Bend,
+                MidiMessage::Aftertouch { .. } | MidiMessage::ChannelAftertouch { .. } => {
+                    Category::Aftertouch
+                }
+            },
+            TrackEventKind::SysEx(_) | TrackEventKind::Escape(_) => Category::Sysex,
+            TrackEventKind::Meta(_) => Category::Meta,
+        }
+    }
+}
+
+/// Returns the channel of MIDI channel messages.
+pub fn channel(kind: &TrackEventKind) -> Option<u4> {
+    match kind {
+        TrackEventKind::Midi { channel, .. } => Some(*channel),
+        _ => None,
+    }
+}
+
+/// Parses an inclusive range in *low..high* format, or a single value.
+fn parse_range(s: &str, parse: impl Fn(&str) -> Option<u7>) -> Result<RangeInclusive<u7>, String> {
+    let parse = |s: &str| parse(s).ok_or_else(|| format!("invalid value: `{s}`"));
+    let (low, high) = match s.split_once("..") {
+        Some((low, high)) => (parse(low)?, parse(high)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if low > high {
+        return Err(format!("`{low}` is bigger than `{high}`"));
+    }
+    Ok(low..=high)
+}
+
+/// Inclusive range of 7-bit values, as in velocities or controller values.
+#[derive(Clone, Debug)]
+pub struct ValueRange(pub RangeInclusive<u7>);
+
+impl FromStr for ValueRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_range(s, |s| s.parse::<u8>().ok().and_then(u7::try_from)).map(Self)
+    }
+}
+
+/// Inclusive range of keys, specified as either key numbers or note names.
+#[derive(Clone, Debug)]
+pub struct KeyRange(pub RangeInclusive<u7>);
+
+impl FromStr for KeyRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse =
+            |s: &str| names::parse_note(s).or_else(|| s.parse::<u8>().ok().and_then(u7::try_from));
+        parse_range(s, parse).map(Self)
+    }
+}
@@ -0,0 +1,346 @@
+//! Event filtering using predicate expressions.
+
+use std::{error::Error, io, ops::Range};
+
+use clap::ValueEnum;
+use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
+
+use crate::{
+    event::{self, Category, KeyRange, ValueRange},
+    manip,
+    time::{total_pulse_of_range, PulseOrBeat, TempoMap},
+};
+
+pub enum Expr {
+    Kind(Category),
+    Channel(Vec<ValueRange>),
+    Controller(Vec<ValueRange>),
+    Value(Vec<ValueRange>),
+    Key(Vec<KeyRange>),
+    Velocity(Vec<ValueRange>),
+    Track(Vec<usize>),
+    Time(Range<u64>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// An event together with its position in the sequence.
+struct Context<'a> {
+    track: usize,
+    pulse: u64,
+    kind: &'a TrackEventKind<'a>,
+}
+
+fn parse_list<R: std::str::FromStr<Err = String>>(s: &str) -> Result<Vec<R>, String> {
+    s.split(',').map(str::parse).collect()
+}
+
+impl Expr {
+    fn matches(&self, ctx: &Context) -> bool {
+        let message = match ctx.kind {
+            TrackEventKind::Midi { message, .. } => Some(message),
+            _ => None,
+        };
+        let contains = |ranges: &[ValueRange], value| ranges.iter().any(|r| r.0.contains(&value));
+        match self {
+            Expr::Kind(category) => Category::of(ctx.kind) == *category,
+            Expr::Channel(ranges) => event::channel(ctx.kind)
+                .is_some_and(|ch| contains(ranges, (ch.as_int() + 1).into())),
+            Expr::Controller(ranges) => match message {
+                Some(MidiMessage::Controller { controller, .. }) => contains(ranges, *controller),
+                _ => false,
+            },
+            Expr::Value(ranges) => match message {
+                Some(MidiMessage::Controller { value, .. }) => contains(ranges, *value),
+                _ => false,
+            },
+            Expr::Key(ranges) => match message {
+                Some(
+                    MidiMessage::NoteOff { key, .. }
+                    | MidiMessage::NoteOn { key, .. }
+                    | MidiMessage::Aftertouch { key, .. },
+                ) => ranges.iter().any(|r| r.0.contains(key)),
+                _ => false,
+            },
+            Expr::Velocity(ranges) => match message {
+                Some(
+                    MidiMessage::NoteOff { vel, .. }
+                    | MidiMessage::NoteOn { vel, .. }
+                    | MidiMessage::Aftertouch { vel, .. }
+                    | MidiMessage::ChannelAftertouch { vel },
+                ) => contains(ranges, *vel),
+                _ => false,
+            },
+            Expr::Track(tracks) => tracks.contains(&ctx.track),
+            Expr::Time(range) => range.contains(&ctx.pulse),
+            Expr::Not(expr) => !expr.matches(ctx),
+            Expr::And(a, b) => a.matches(ctx) && b.matches(ctx),
+            Expr::Or(a, b) => a.matches(ctx) || b.matches(ctx),
+        }
+    }
+}
+
+/// Recursive-descent parser over whitespace- and parenthesis-separated tokens.
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    i: usize,
+    tempo_map: &'a TempoMap,
+    samplerate: Option<u32>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.i).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let ret = self.peek();
+        self.i += 1;
+        ret
+    }
+
+    fn or(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut ret = self.and()?;
+        while self.peek() == Some("or") {
+            self.i += 1;
+            ret = Expr::Or(Box::new(ret), Box::new(self.and()?));
+        }
+        Ok(ret)
+    }
+
+    fn and(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut ret = self.not()?;
+        while self.peek() == Some("and") {
+            self.i += 1;
+            ret = Expr::And(Box::new(ret), Box::new(self.not()?));
+        }
+        Ok(ret)
+    }
+
+    fn not(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.next() {
+            Some("not") => Ok(Expr::Not(Box::new(self.not()?))),
+            Some("(") => {
+                let ret = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(ret),
+                    _ => Err("missing `)`".into()),
+                }
+            }
+            Some(token) => self.predicate(token),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+
+    fn predicate(&self, token: &str) -> Result<Expr, Box<dyn Error>> {
+        let Some((field, value)) = token.split_once('=') else {
+            return match Category::from_str(token, false) {
+                Ok(category) => Ok(Expr::Kind(category)),
+                Err(_) => Err(format!("unknown event kind: `{token}`").into()),
+            };
+        };
+        Ok(match field {
+            "ch" => {
+                let ranges: Vec<ValueRange> = parse_list(value)?;
+                if ranges
+                    .iter()
+                    .any(|r| (r.0.start().as_int() < 1) || (r.0.end().as_int() > 16))
+                {
+                    return Err(format!("channels must be between 1 and 16: `{value}`").into());
+                }
+                Expr::Channel(ranges)
+            }
+            "cc" => Expr::Controller(parse_list(value)?),
+            "value" => Expr::Value(parse_list(value)?),
+            "key" => Expr::Key(parse_list(value)?),
+            "vel" => Expr::Velocity(parse_list(value)?),
+            "track" => Expr::Track(value.split(',').map(str::parse).collect::<Result<_, _>>()?),
+            "time" => {
+                let Some((start, end)) = value.split_once("..") else {
+                    return Err(
+                        format!("time ranges must be in `start..end` format: `{value}`").into(),
+                    );
+                };
+                let pos = |s: &str| s.parse::<PulseOrBeat>().map_err(|e| e.to_string());
+                let start = pos(if start.is_empty() { "0" } else { start })?;
+                let end = (!end.is_empty()).then(|| pos(end)).transpose()?;
+                let (start, end) =
+                    total_pulse_of_range(&start, &end, self.tempo_map, self.samplerate)?;
+                Expr::Time(start..end.unwrap_or(u64::MAX))
+            }
+            _ => return Err(format!("unknown field: `{field}`").into()),
+        })
+    }
+}
+
+/// Parses a predicate expression, resolving all time ranges using the given tempo map.
+pub fn parse(
+    s: &str,
+    tempo_map: &TempoMap,
+    samplerate: Option<u32>,
+) -> Result<Expr, Box<dyn Error>> {
+    let mut tokens = vec![];
+    for word in s.split_whitespace() {
+        let mut rest = word;
+        while let Some(i) = rest.find(['(', ')']) {
+            if i > 0 {
+                tokens.push(&rest[..i]);
+            }
+            tokens.push(&rest[i..(i + 1)]);
+            rest = &rest[(i + 1)..];
+        }
+        if !rest.is_empty() {
+            tokens.push(rest);
+        }
+    }
+    let mut parser = Parser {
+        tokens,
+        i: 0,
+        tempo_map,
+        samplerate,
+    };
+    let ret = parser.or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected `{token}`").into());
+    }
+    Ok(ret)
+}
+
+/// Removes all events matching the expression, or all events not matching it if `invert` is
+/// true. *End of Track* events are always kept.
+pub fn filter(smf: &Smf, expr: &Expr, invert: bool) -> Result<(), Box<dyn Error>> {
+    let mut filtered_smf = Smf {
+        header: smf.header,
+        tracks: vec![],
+    };
+
+    for (track_i, track) in smf.tracks.iter().enumerate() {
+        let track = manip::remove_events(track, |_, pulse, ev| {
+            let ctx = Context {
+                track: track_i,
+                pulse,
+                kind: &ev.kind,
+            };
+            let is_end_of_track = matches!(ev.kind, TrackEventKind::Meta(MetaMessage::EndOfTrack));
+            !is_end_of_track && (expr.matches(&ctx) ^ invert)
+        });
+        filtered_smf.tracks.push(track);
+    }
+
+    Ok(filtered_smf.write_std(io::stdout())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{Format, Header, Timing, TrackEvent};
+
+    use super::*;
+
+    fn note_on(channel: u8, key: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: (channel - 1).into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        }
+    }
+
+    fn cc(channel: u8, controller: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: (channel - 1).into(),
+            message: MidiMessage::Controller {
+                controller: controller.into(),
+                value: 64.into(),
+            },
+        }
+    }
+
+    fn parse_with_tempo_map(s: &str) -> Result<Expr, Box<dyn Error>> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(480.into()),
+        ));
+        smf.tracks.push(vec![TrackEvent {
+            delta: 3840.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        }]);
+        parse(s, &TempoMap::new(&smf), None)
+    }
+
+    fn matches(s: &str, track: usize, pulse: u64, kind: TrackEventKind) -> bool {
+        let ctx = Context {
+            track,
+            pulse,
+            kind: &kind,
+        };
+        parse_with_tempo_map(s).unwrap().matches(&ctx)
+    }
+
+    #[test]
+    fn precedence() {
+        // `and` binds tighter than `or`.
+        let expr = "note or cc and ch=2";
+        assert!(matches(expr, 0, 0, note_on(1, 60)));
+        assert!(!matches(expr, 0, 0, cc(1, 7)));
+        assert!(matches(expr, 0, 0, cc(2, 7)));
+
+        let expr = "(note or cc) and ch=2";
+        assert!(!matches(expr, 0, 0, note_on(1, 60)));
+        assert!(matches(expr, 0, 0, note_on(2, 60)));
+
+        // `not` binds tighter than `and`.
+        let expr = "not note and ch=1";
+        assert!(matches(expr, 0, 0, cc(1, 7)));
+        assert!(!matches(expr, 0, 0, note_on(1, 60)));
+        assert!(!matches(expr, 0, 0, cc(2, 7)));
+
+        let expr = "not (note and ch=1)";
+        assert!(matches(expr, 0, 0, note_on(2, 60)));
+        assert!(!matches(expr, 0, 0, note_on(1, 60)));
+
+        assert!(matches("not not note", 0, 0, note_on(1, 60)));
+    }
+
+    #[test]
+    fn predicates() {
+        assert!(matches("cc=91,93", 0, 0, cc(3, 93)));
+        assert!(!matches("cc=91,93", 0, 0, cc(3, 92)));
+        assert!(matches("key=C4..E4", 0, 0, note_on(1, 64)));
+        assert!(!matches("key=C4..E4", 0, 0, note_on(1, 65)));
+        assert!(!matches("key=0..127", 0, 0, cc(1, 60)));
+        assert!(matches("ch=1..3,16", 0, 0, cc(16, 7)));
+        assert!(matches("track=1,2", 2, 0, cc(1, 7)));
+        assert!(!matches("track=1,2", 0, 0, cc(1, 7)));
+
+        let expr = "time=480..960";
+        assert!(!matches(expr, 0, 479, cc(1, 7)));
+        assert!(matches(expr, 0, 480, cc(1, 7)));
+        assert!(!matches(expr, 0, 960, cc(1, 7)));
+        assert!(matches("time=..b2", 0, 0, cc(1, 7)));
+        assert!(!matches("time=..b2", 0, 1920, cc(1, 7)));
+        assert!(matches("time=b2..", 0, 1920, cc(1, 7)));
+    }
+
+    #[test]
+    fn errors() {
+        for s in [
+            "",
+            "note and",
+            "and note",
+            "(note",
+            "note)",
+            "note note",
+            "foo",
+            "foo=1",
+            "ch=0",
+            "ch=17",
+            "key=H4",
+            "time=960..480",
+            "time=480",
+        ] {
+            assert!(parse_with_tempo_map(s).is_err(), "{s}");
+        }
+    }
+}
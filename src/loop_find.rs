@@ -1,11 +1,17 @@
 //! Loop detection.
 
-use std::collections::HashSet;
+use std::{collections::HashSet, error::Error};
 
-use midly::{MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use midly::{MidiMessage, Smf, TrackEvent, TrackEventKind};
 use rayon::prelude::*;
+use serde::Serialize;
 
-use crate::{event, state::MidiState, time::MidiTimeDisplay};
+use crate::{
+    event,
+    output::{self, Format},
+    state::MidiState,
+    time::{MidiTimeDisplay, TempoMap, TimeRecord},
+};
 
 #[derive(Clone, Copy, Default)]
 struct Loop {
@@ -18,7 +24,57 @@ impl Loop {
         (self.len > other.len) || ((self.len == other.len) && (self.start < other.start))
     }
 
-    fn print(&self, prefix: &str, timing: &Timing, track: &[TrackEvent], samplerate: Option<u32>) {
+    /// Returns the labeled event indices of the first note and the loop boundaries, in event
+    /// order.
+    fn points(&self, track: &[TrackEvent]) -> Vec<(&'static str, usize)> {
+        let end_1 = self.start + self.len;
+        let mut ret = vec![("Loop start", self.start), ("Loop end", end_1)];
+        if let Some(first_note) = track.iter().position(|ev| event::note_on(ev).is_some()) {
+            if first_note <= end_1 {
+                ret.push(("First note", first_note));
+            }
+        }
+        ret.sort_by_key(|(label, ev_i)| (*ev_i, *label != "First note"));
+        ret
+    }
+
+    fn record(
+        &self,
+        tempo_map: &TempoMap,
+        track: &[TrackEvent],
+        samplerate: Option<u32>,
+    ) -> Option<LoopRecord> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut time = MidiTimeDisplay::new(tempo_map, track, samplerate);
+        let mut point_at = |ev_i: usize| {
+            time.time = tempo_map.time_at(pulse_of_event(track, ev_i));
+            LoopPoint {
+                event: ev_i,
+                time: time.record(),
+            }
+        };
+        let first_note = self
+            .points(track)
+            .into_iter()
+            .find(|(label, _)| *label == "First note")
+            .map(|(_, ev_i)| point_at(ev_i));
+        Some(LoopRecord {
+            len: self.len,
+            first_note,
+            start: point_at(self.start),
+            end: point_at(self.start + self.len),
+        })
+    }
+
+    fn print(
+        &self,
+        prefix: &str,
+        tempo_map: &TempoMap,
+        track: &[TrackEvent],
+        samplerate: Option<u32>,
+    ) {
         if self.len == 0 {
             println!("No loop found.");
             return;
@@ -32,27 +88,41 @@ impl Loop {
         );
 
         let event_width = (track.len().ilog10() + 1) as usize;
-        let mut first_note_seen = false;
-        let mut time = MidiTimeDisplay::new(timing, track, samplerate);
-        for (ev_i, ev) in track.iter().enumerate() {
-            time.time = time.time + ev;
-            if !first_note_seen && event::note_on(ev).is_some() {
-                println!("First note: event {ev_i:>event_width$} / {time}");
-                first_note_seen = true;
-            }
-            if ev_i == start {
-                println!("Loop start: event {ev_i:>event_width$} / {time}");
-            } else if ev_i == end_1 {
-                println!("  Loop end: event {ev_i:>event_width$} / {time}");
-                return;
-            }
+        let mut time = MidiTimeDisplay::new(tempo_map, track, samplerate);
+        for (label, ev_i) in self.points(track) {
+            time.time = tempo_map.time_at(pulse_of_event(track, ev_i));
+            println!("{label:>10}: event {ev_i:>event_width$} / {time}");
         }
     }
 }
 
+/// Returns the total MIDI pulse of the event at the given index.
+fn pulse_of_event(track: &[TrackEvent], ev_i: usize) -> u64 {
+    track
+        .iter()
+        .take(ev_i + 1)
+        .map(|ev| ev.delta.as_int() as u64)
+        .sum()
+}
+
+#[derive(Serialize)]
+struct LoopPoint {
+    event: usize,
+    time: TimeRecord,
+}
+
+#[derive(Serialize)]
+struct LoopRecord {
+    len: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_note: Option<LoopPoint>,
+    start: LoopPoint,
+    end: LoopPoint,
+}
+
 fn loop_contains_itself(track: &[TrackEvent], found_loop: &Loop) -> bool {
     let track_at_loop_start = track.iter().skip(found_loop.start);
-    for factor in (2..(found_loop.len / 2) + 1).filter(|&x| found_loop.len % x == 0) {
+    for factor in (2..(found_loop.len / 2) + 1).filter(|&x| found_loop.len.is_multiple_of(x)) {
         let section_len = found_loop.len / factor;
         let section_is_repeated = (1..factor).all(|section_i| {
             let a = track_at_loop_start.clone().take(section_len);
@@ -189,14 +259,16 @@ fn find_loop_ending_at(
 pub struct Options {
     pub samplerate: Option<u32>,
     pub shift: Option<u64>,
+    pub format: Format,
 }
 
-pub fn find(smf: &Smf, opts: Options) -> Result<(), String> {
+pub fn find(smf: &Smf, tempo_map: &TempoMap, opts: Options) -> Result<(), Box<dyn Error>> {
     if smf.tracks.len() != 1 {
         return Err(format!(
             "only implemented for single-track sequences (sequence has {} tracks); try flattening with the `smf0` command",
             smf.tracks.len()
-        ));
+        )
+        .into());
     }
 
     let track = &smf.tracks[0];
@@ -208,30 +280,49 @@ pub fn find(smf: &Smf, opts: Options) -> Result<(), String> {
         })
         .reduce(Loop::default, |a, b| if a.better_than(&b) { a } else { b });
 
-    note_loop.print("Best loop in note space:", &smf.header.timing, track, None);
-
-    if note_loop.len != 0 && (opts.samplerate.is_some() || opts.shift.is_some()) {
-        let shift_i = if let Some(shift) = opts.shift {
-            let mut acc = 0_u64;
-            track
-                .iter()
-                .skip(note_loop.start + 1)
-                .position(|ev| {
-                    acc += ev.delta.as_int() as u64;
-                    acc >= shift
-                })
-                .unwrap_or(note_loop.start)
+    let recording_loop =
+        if note_loop.len != 0 && (opts.samplerate.is_some() || opts.shift.is_some()) {
+            let shift_i = if let Some(shift) = opts.shift {
+                let mut acc = 0_u64;
+                track
+                    .iter()
+                    .skip(note_loop.start + 1)
+                    .position(|ev| {
+                        acc += ev.delta.as_int() as u64;
+                        acc >= shift
+                    })
+                    .unwrap_or(note_loop.start)
+            } else {
+                0
+            };
+            let start = note_loop.start + shift_i;
+
+            Some(
+                ((start + note_loop.len)..track.len())
+                    .find_map(|cursor| find_loop_ending_at(cursor, start, 0, track, true))
+                    .unwrap_or_default(),
+            )
         } else {
-            0
+            None
         };
-        let start = note_loop.start + shift_i;
 
-        let recording_loop = ((start + note_loop.len)..track.len())
-            .find_map(|cursor| find_loop_ending_at(cursor, start, 0, track, true))
-            .unwrap_or_default();
+    if opts.format == Format::Json {
+        #[derive(Serialize)]
+        struct Output {
+            note_space: Option<LoopRecord>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            recording_space: Option<Option<LoopRecord>>,
+        }
+        return Ok(output::print_json(&Output {
+            note_space: note_loop.record(tempo_map, track, opts.samplerate),
+            recording_space: recording_loop.map(|l| l.record(tempo_map, track, opts.samplerate)),
+        })?);
+    }
 
+    note_loop.print("Best loop in note space:", tempo_map, track, None);
+    if let Some(recording_loop) = recording_loop {
         print!("\nBest loop in recording space: ");
-        recording_loop.print("", &smf.header.timing, track, opts.samplerate);
+        recording_loop.print("", tempo_map, track, opts.samplerate);
     }
 
     Ok(())
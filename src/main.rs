@@ -1,21 +1,33 @@
+mod asm;
+mod diff;
 mod dump;
+mod equiv;
 mod event;
+mod filter;
 mod loop_find;
 mod manip;
+mod names;
+mod notes;
+mod output;
 mod smf;
 mod state;
+mod sysex;
 mod time;
 
 use std::{
     error::Error,
     io::{self, Read},
+    path::PathBuf,
     sync::OnceLock,
 };
 
 use clap::{Parser, Subcommand};
 use midly::Smf;
 
-use crate::time::{total_pulse_of_range, PulseOrBeat};
+use crate::{
+    output::Format,
+    time::{total_pulse_of_range, PulseOrBeat, TempoMap},
+};
 
 struct HelpTemplate {
     with_bp: bool,
@@ -49,6 +61,23 @@ With a PPQN value of 480:
 • 4:240 (→ 2160)
 • 4:    (→ 1920)
 •  :240 (→  240)
+
+Positions can also be specified in 1-based bar.beat.tick format with a `b` prefix, according to the *Time Signature* events of the sequence and assuming 4/4 time until the first one. A time signature change in the middle of a bar starts a new bar. Omitted beats default to 1, and omitted ticks to 0:
+
+• b17.1.0
+• b17.2
+• b17
+
+Sequences with SMPTE timing have no beats, and instead accept SMPTE timecodes in hours:minutes:seconds:frames.subframes format. Any omitted component is 0. 29.97 FPS timecodes use drop-frame numbering. With 25 FPS and 40 subframes per frame:
+
+• 0:01:23:12.20 (→ 83500)
+• :1:23:12      (→ 83480)
+
+Realtime positions can be specified in [[hours:]minutes:]seconds[.fraction] format with an `s` suffix, and PCM sample positions with an `s` prefix. Both are rounded to the nearest pulse according to the tempo map of the sequence, and the resulting position is reported on stderr. Sample positions require the global `-r`/`--samplerate` option.
+
+• 1:23.456s
+• 83.456s
+• s123456
 ", opts
             )
             })
@@ -73,13 +102,49 @@ fn help() -> HelpTemplate {
 
 #[derive(Subcommand)]
 enum CliCommand {
+    /// Assembles a sequence from the text format written by `dump --assembly`, and writes the
+    /// resulting MIDI to stdout.
+    ///
+    /// The text format is line-based, and `#` starts a comment that runs until the end of the line.
+    /// The first line describes the SMF header, as in `smf format=1 ppqn=480` or `smf format=0
+    /// fps=29.97 subframes=80`. Every `track` line starts a new track, and each following line
+    /// describes one event as its delta time, its type, its parameters as `key=value` pairs, and
+    /// any data bytes as two-digit hexadecimal values or quoted strings, as in `+240 note_on
+    /// channel=1 key=60 vel=100` or `+0 sys_ex 7E 7F 09 01 F7`. The event types and parameter
+    /// names match the JSON output of the `dump` command. Strings are encoded as UTF-8, and support
+    /// the `\"`, `\\`, `\n`, `\r`, `\t`, and `\xNN` escapes.
+    ///
+    /// Assembling the output of `dump --assembly` results in a sequence with identical events.
+    #[command(help_template = help())]
+    Assemble,
+
+    /// Shows the given positions in all supported time units.
+    ///
+    /// For each position, this command also shows the active tempo and time signature, and the
+    /// index of the first event at or after the position on every track.
+    #[command(help_template = help().with_bp())]
+    At {
+        /// Positions to show.
+        #[arg(value_name = "B/P", required = true)]
+        positions: Vec<PulseOrBeat>,
+    },
+
     /// Removes MIDI events within a certain range, and writes the new MIDI to stdout.
     ///
-    /// Despite the beat/pulse parameters, this command is extremely basic, and simply removes the
-    /// events that are closest to the given time points. It inserts no *Note Off* events for notes
-    /// that might be playing at the cut point, nor modifies any delta times to re-synchronize
-    /// multi-track sequences; you might want to flatten the latter using the `smf0` command
-    /// beforehand.
+    /// The range is removed from every track: All events at or after its start and before its end
+    /// are removed, and all later events move earlier by the length of the range, which keeps the
+    /// tracks of multi-track sequences in sync. *End of Track* events within the range move to its
+    /// start. By default, the command inserts no *Note Off* events for notes that might be playing
+    /// at the cut point.
+    ///
+    /// With `-c`/`--chase`, the command keeps the playback state intact across the cut on every
+    /// track. It inserts *Note Off* events at the cut point for all notes that are still sounding
+    /// there, and removes the original *Note Off* events of these notes after the cut. It then
+    /// inserts the minimal set of *Control Change*, *Program Change*, *Pitch Bend*, and *Tempo*
+    /// events that brings each channel and the tempo to the state it would have at the end of the
+    /// cut range in the original sequence. *Bank Select* controllers are inserted before the
+    /// *Program Change* event, and all other controllers after it. Data entry, RPN/NRPN selection,
+    /// and channel mode controllers are not restored.
     #[command(help_template = help().with_bp())]
     Cut {
         /// Start of the cut range.
@@ -89,34 +154,294 @@ enum CliCommand {
         /// End of the cut range. Defaults to the end of the sequence if omitted.
         #[arg(value_name = "B/P")]
         end: Option<PulseOrBeat>,
+
+        /// Release sounding notes and restore the channel and tempo state across the cut.
+        #[arg(short = 'c', long)]
+        chase: bool,
     },
 
-    /// Dumps all MIDI events to stdout, with one event per line.
+    /// Compares the sequence in stdin against another sequence, and lists their differences.
     ///
-    /// For easier navigation, the output also contains the total MIDI pulse count and the 0-based
-    /// *quarter-note:pulse* beat number in separate columns.
+    /// The output shows any differences in the SMF format, the number of tracks, and the timing,
+    /// followed by any differences in the effective tempo changes of the whole sequence and the
+    /// differences in the events of every track. The latter two require both sequences to use the
+    /// same timing, i.e., the same PPQN value or SMPTE frame rate and resolution; for sequences
+    /// with different timings, only the header differences are shown before exiting with an error. Tracks are compared by their index, and events by
+    /// their total pulse, regardless of their order within the same pulse. Events at the same pulse
+    /// that only differ in their values are shown as changed, as in a *Note On* event for the same
+    /// channel and key with a different velocity, or a *Control Change* event for the same channel
+    /// and controller with a different value. All other events are shown as removed or inserted.
+    ///
+    /// The time columns match those of the `dump` command, including `-R`/`--realtime` and
+    /// `-s`/`--symbolic`, and are calculated using the tempo map of the sequence in stdin. Removed
+    /// events are marked with `-`, inserted events with `+`, and changed events with `~`.
+    /// Identical sequences produce no output.
     #[command(help_template = help())]
-    Dump,
+    Diff {
+        /// Sequence to compare against.
+        #[arg(value_name = "OTHER.mid")]
+        other: PathBuf,
+
+        /// Show the realtime position of every change.
+        #[arg(short = 'R', long)]
+        realtime: bool,
+
+        /// Show channel messages with symbolic names.
+        #[arg(short = 's', long)]
+        symbolic: bool,
+    },
+
+    /// Dumps all MIDI events to stdout, with one event per line.
+    ///
+    /// For easier navigation, the output also contains the total MIDI pulse count, the 0-based
+    /// *quarter-note:pulse* beat number, and the 1-based *bar.beat.tick* position according to the
+    /// *Time Signature* events in separate columns. Sequences with SMPTE timing show the SMPTE
+    /// timecode in place of the beat number, and omit the bar column. `-R`/`--realtime` adds a
+    /// realtime column, and the global `-r`/`--samplerate` option adds a PCM sample column, both
+    /// calculated using the *Tempo* events of all tracks.
+    ///
+    /// With `-s`/`--symbolic`, channel messages are shown in a compact form with 1-based channel
+    /// numbers, note names (with key 60 being C4), General MIDI drum names on channel 10, General
+    /// MIDI instrument names, standard controller names, and signed pitch bend values.
+    ///
+    /// With `--format json`, every event additionally carries its realtime and sample position, and
+    /// its fields as separate keys with 1-based MIDI channel numbers.
+    ///
+    /// Meta text events are decoded according to `-e`/`--encoding`, and shown as hex bytes if they
+    /// are invalid in that encoding. Common General MIDI, Roland GS, and Yamaha XG *SysEx* messages
+    /// are followed by a description, which also points out Roland checksum mismatches.
+    ///
+    /// The output can be narrowed down to certain tracks, channels, and kinds of events, as well as
+    /// to a range. All positions are still calculated from the full sequence.
+    ///
+    /// With `-a`/`--assembly`, the command instead writes only the delta times and events in the
+    /// text format read by the `assemble` command, which allows sequences to be edited in a text
+    /// editor and kept under version control.
+    #[command(help_template = help().with_bp())]
+    Dump {
+        /// Write the text format read by the `assemble` command.
+        #[arg(
+            short = 'a',
+            long,
+            conflicts_with_all = [
+                "tracks", "channels", "kinds", "start", "end", "realtime", "symbolic", "encoding"
+            ]
+        )]
+        assembly: bool,
+
+        /// Character encoding of meta text events. Events that are invalid in this encoding are
+        /// shown as hex bytes.
+        #[arg(short = 'e', long, value_enum, default_value_t)]
+        encoding: dump::Encoding,
+
+        /// Show the realtime position of every event.
+        #[arg(short = 'R', long)]
+        realtime: bool,
+
+        /// Show channel messages with symbolic names.
+        #[arg(short = 's', long)]
+        symbolic: bool,
+
+        /// Only show the given 0-based tracks.
+        #[arg(
+            short = 't',
+            long = "track",
+            value_name = "TRACK",
+            value_delimiter = ','
+        )]
+        tracks: Vec<usize>,
+
+        /// Only show events on the given 1-based MIDI channels. This hides all events without a
+        /// channel.
+        #[arg(
+            short = 'c',
+            long = "channel",
+            value_name = "CHANNEL",
+            value_delimiter = ',',
+            value_parser = clap::value_parser!(u8).range(1..=16)
+        )]
+        channels: Vec<u8>,
+
+        /// Only show events of the given kinds.
+        #[arg(
+            short = 'k',
+            long = "kind",
+            value_name = "KIND",
+            value_enum,
+            value_delimiter = ','
+        )]
+        kinds: Vec<event::Category>,
+
+        /// Start of the shown range.
+        #[arg(value_name = "B/P")]
+        start: Option<PulseOrBeat>,
+
+        /// End of the shown range. Defaults to the end of the sequence if omitted.
+        #[arg(value_name = "B/P")]
+        end: Option<PulseOrBeat>,
+    },
 
     /// Prints the total duration of the sequence.
     ///
-    /// This command only examines the track with the highest final MIDI pulse value. Multi-track
-    /// sequences might have their tempo events on a different track, which will cause all realtime
-    /// values to be omitted. In that case, you will need to flatten the sequence using the `smf0`
-    /// command beforehand.
+    /// The duration is measured at the end of the track with the highest final MIDI pulse value.
+    /// Realtime values are calculated using the *Tempo* events of all tracks, and assume the
+    /// default tempo of 120 BPM until the first *Tempo* event.
+    ///
+    /// The extended report additionally shows the positions of the first and last note, the silence
+    /// before the first note and after the last *Note Off* event, and the end of each track together
+    /// with the gap between its last *Note Off* event and its *End of Track* event.
     #[command(help_template = help())]
-    Duration,
+    Duration {
+        /// Show the extended report.
+        #[arg(short = 'x', long)]
+        extended: bool,
+    },
 
-    /// Removes all note events within the given range, and writes the modified MIDI to stdout.
+    /// Checks whether the sequence in stdin plays identically to another sequence.
+    ///
+    /// Two sequences are equivalent if they send the same MIDI messages at the same times, after
+    /// both have been normalized as follows:
+    ///
+    /// * All tracks are merged into one, and meta events are ignored.
+    ///
+    /// * *Note Off* events and *Note On* events with zero velocity are treated as the same, and
+    ///   release velocities are ignored.
+    ///
+    /// * *Control Change* and *Pitch Bend* events that don't change the current value of their
+    ///   channel are ignored.
     ///
-    /// This only removes *Note On* events with nonzero velocity. Any playing notes at the start or
-    /// end of the removal range are left playing.
+    /// * Simultaneous events are compared regardless of their order if the order doesn't matter:
+    ///   events on different channels, notes for different keys, and controller changes for
+    ///   different controllers. SysEx events and all other channel messages keep their relative
+    ///   order.
+    ///
+    /// Running status is resolved when reading a sequence, and therefore never affects the result.
+    /// By default, events are compared by their pulse, scaled to a common PPQN value, and
+    /// effective tempo changes are compared as well. With `-R`/`--realtime`, events are instead
+    /// compared by their exact realtime position, which also allows sequences with different
+    /// tempo maps or SMPTE timings to be equivalent.
+    ///
+    /// If the sequences are not equivalent, the first audible difference is printed, and the
+    /// command exits with a non-zero status.
+    #[command(help_template = help())]
+    Equiv {
+        /// Sequence to compare against.
+        #[arg(value_name = "OTHER.mid")]
+        other: PathBuf,
+
+        /// Compare events by their realtime position.
+        #[arg(short = 'R', long)]
+        realtime: bool,
+    },
+
+    /// Removes all events matching the given expression, and writes the modified MIDI to stdout.
+    ///
+    /// The expression combines the following predicates with `and`, `or`, `not`, and parentheses.
+    /// `not` binds tighter than `and`, which binds tighter than `or`.
+    ///
+    /// * `note`, `cc`, `program`, `bend`, `aftertouch`, `sysex`, `meta`: Event kind, with the same
+    ///   categories as in `dump -k`.
+    ///
+    /// * `ch=LIST`: 1-based MIDI channel.
+    ///
+    /// * `cc=LIST`: Controller number of a *Control Change* event.
+    ///
+    /// * `value=LIST`: Value of a *Control Change* event.
+    ///
+    /// * `key=LIST`: Key of a note or polyphonic aftertouch event.
+    ///
+    /// * `vel=LIST`: Velocity of a note event, or pressure of an aftertouch event.
+    ///
+    /// * `track=LIST`: 0-based track.
+    ///
+    /// * `time=[B/P]..[B/P]`: Pulse range with an exclusive end. Either side can be omitted to
+    ///   extend the range to the start or end of the sequence.
+    ///
+    /// Lists are comma-separated, and consist of single values or inclusive ranges in `low..high`
+    /// format. Keys can also be specified as note names with key 60 being `C4`. Predicates on
+    /// fields that an event doesn't have never match that event. Predicates must not contain
+    /// spaces. Examples:
+    ///
+    /// * `sysex`
+    ///
+    /// * `cc=91,93 and ch=3`
+    ///
+    /// * `bend and time=b10..b21`
+    ///
+    /// Removed events pass on their delta time to the next retained event, which keeps all
+    /// remaining events at their original position. *End of Track* events are never removed. With
+    /// `-v`/`--invert`, all events that don't match the expression are removed instead.
+    #[command(help_template = help().with_bp())]
+    Filter {
+        /// Retain matching events instead of removing them.
+        #[arg(short = 'v', long)]
+        invert: bool,
+
+        /// Predicate expression.
+        #[arg(value_name = "EXPR")]
+        expr: String,
+    },
+
+    /// Removes all notes within the given range, and writes the modified MIDI to stdout.
+    ///
+    /// Every removed note is removed together with the *Note Off* event that releases it, with
+    /// notes being paired in the same way as in the `notes` command. By default, notes are removed
+    /// if their *Note On* event lies within the removal range, and all other notes are left
+    /// intact. `--crossing` changes the treatment of notes that are only partially within the
+    /// removal range: `truncate` additionally cuts off notes that started before the removal range
+    /// and are still playing when it starts, by replacing their *Note Off* event with a new one at
+    /// the boundary. `keep` only removes notes that lie completely within the removal range. With
+    /// `-v`/`--invert`, the removal range is everything outside the given range.
+    ///
+    /// The removed notes can be further narrowed down by their track, their channel, their key, and
+    /// the velocity of their *Note On* event. Each of these options takes a comma-separated list,
+    /// and a note must match one entry of every given list. Keys and velocities are specified as
+    /// either single values or inclusive ranges in `low..high` format, and keys can also be
+    /// specified as note names with key 60 being `C4`, as in `-k 42,44,46` or `-k C4..B4`. With
+    /// `-v`/`--invert`, all notes that don't match these options are removed as well, which retains
+    /// only the matching notes within the given range.
     #[command(help_template = help().with_bp())]
     FilterNote {
         /// Retain notes in the range instead of removing them.
         #[arg(short = 'v', long)]
         invert: bool,
 
+        /// Treatment of notes that cross the boundary of the removal range.
+        #[arg(short = 'x', long, value_enum, default_value_t)]
+        crossing: manip::Crossing,
+
+        /// Only remove notes on the given 0-based tracks.
+        #[arg(
+            short = 't',
+            long = "track",
+            value_name = "TRACK",
+            value_delimiter = ','
+        )]
+        tracks: Vec<usize>,
+
+        /// Only remove notes on the given 1-based MIDI channels.
+        #[arg(
+            short = 'c',
+            long = "channel",
+            value_name = "CHANNEL",
+            value_delimiter = ',',
+            value_parser = clap::value_parser!(u8).range(1..=16)
+        )]
+        channels: Vec<u8>,
+
+        /// Only remove notes with the given keys or key ranges.
+        #[arg(short = 'k', long = "key", value_name = "KEYS", value_delimiter = ',')]
+        keys: Vec<event::KeyRange>,
+
+        /// Only remove notes with the given velocities or velocity ranges.
+        #[arg(
+            short = 'V',
+            long = "velocity",
+            value_name = "VELOCITIES",
+            value_delimiter = ','
+        )]
+        velocities: Vec<event::ValueRange>,
+
         /// Start of the removal range.
         #[arg(value_name = "B/P")]
         start: PulseOrBeat,
@@ -166,6 +491,31 @@ enum CliCommand {
         start: PulseOrBeat,
     },
 
+    /// Lists all notes of the sequence, pairing every *Note On* event with the *Note Off* event that
+    /// releases it.
+    ///
+    /// Notes are paired per channel and key across all tracks, with *Note On* events of zero
+    /// velocity also counting as *Note Off* events. If a channel and key is struck again while the
+    /// previous note is still sounding, the next *Note Off* event releases the earlier note, and the
+    /// earlier note is flagged as retriggered. Notes that are never released last until the end of
+    /// the sequence and are flagged as such.
+    ///
+    /// For each note, the output shows its track and event index, its start position in all time
+    /// units, its length in all time units except bars and SMPTE timecodes, its 1-based channel,
+    /// its key, its velocity, and the release velocity of *Note Off* events.
+    ///
+    /// With `-p`, note lengths include the time a note is held by the sustain (CC64) or sostenuto
+    /// (CC66) pedal of its channel after its *Note Off* event, which yields the actual sounding
+    /// duration. A pedal counts as pressed at values of 64 and above. Sostenuto only holds notes
+    /// that were sounding when the pedal was pressed. Striking the same key again ends a held note.
+    /// Notes extended this way are flagged as sustained.
+    #[command(help_template = help())]
+    Notes {
+        /// Extend note lengths by the time they are held by the sustain or sostenuto pedal.
+        #[arg(short = 'p', long)]
+        pedals: bool,
+    },
+
     /// Flattens the sequence into a single track and writes the result as SMF Type 0 to stdout.
     ///
     /// With the exception of any *End of Track* events before the final one, all events are
@@ -174,6 +524,28 @@ enum CliCommand {
     /// track order of the input sequence.
     #[command(help_template = help())]
     Smf0,
+
+    /// Shows the state of all channels at the given position.
+    ///
+    /// The state is determined by replaying all events of all tracks up to and including the given
+    /// position. For every channel that deviates from the initial state or has notes sounding, the
+    /// output shows the program, all controllers, and the pitch bend value if they differ from
+    /// their General MIDI power-on defaults, followed by the notes currently sounding. These
+    /// include notes held by the sustain or sostenuto pedal after their *Note Off* event. The
+    /// output also shows the tempo active at the position.
+    #[command(help_template = help().with_bp())]
+    State {
+        /// Position to show the state at.
+        #[arg(value_name = "B/P")]
+        pos: PulseOrBeat,
+    },
+
+    /// Lists all *Tempo*, *Time Signature*, and *Key Signature* events of all tracks in order.
+    ///
+    /// Besides the position of each event, the output also shows how long each section lasts until
+    /// the next event of the same type or the end of the sequence.
+    #[command(help_template = help())]
+    TempoMap,
 }
 
 #[derive(Parser)]
@@ -192,10 +564,14 @@ enum CliCommand {
     )
 )]
 struct Cli {
-    /// Sampling rate used for converting times to PCM samples
+    /// Sampling rate used for converting times to PCM samples, rounded to the nearest sample
     #[arg(short = 'r', long)]
     samplerate: Option<u32>,
 
+    /// Output format of reports. Commands that write MIDI data to stdout ignore this option.
+    #[arg(short = 'f', long, value_enum, default_value_t)]
+    format: Format,
+
     #[command(subcommand)]
     command: CliCommand,
 }
@@ -203,29 +579,124 @@ struct Cli {
 fn run(args: Cli) -> Result<(), Box<dyn Error>> {
     let mut bytes = Vec::new();
     io::stdin().lock().read_to_end(&mut bytes)?;
+    if let CliCommand::Assemble = args.command {
+        return asm::assemble(std::str::from_utf8(&bytes)?);
+    }
     let mut smf = Smf::parse(&bytes)?;
-    let timing = smf.header.timing;
+    let tempo_map = TempoMap::new(&smf);
+    let samplerate = args.samplerate;
 
     match args.command {
-        CliCommand::Cut { start, end } => {
-            manip::cut(&mut smf, total_pulse_of_range(&start, &end, &timing)?)?
+        CliCommand::At { positions } => {
+            time::at(&smf, &tempo_map, &positions, samplerate, args.format)?
         }
-        CliCommand::Dump => dump::dump(&smf),
-        CliCommand::Duration => time::duration(&smf, args.samplerate),
-        CliCommand::FilterNote { start, end, invert } => {
-            manip::filter_note(&smf, total_pulse_of_range(&start, &end, &timing)?, invert)?
+        CliCommand::Cut { start, end, chase } => manip::cut(
+            &mut smf,
+            total_pulse_of_range(&start, &end, &tempo_map, samplerate)?,
+            chase,
+        )?,
+        CliCommand::Assemble => unreachable!(),
+        CliCommand::Diff {
+            other,
+            realtime,
+            symbolic,
+        } => {
+            let other_bytes = std::fs::read(other)?;
+            let other = Smf::parse(&other_bytes)?;
+            let opts = diff::Options {
+                samplerate,
+                realtime,
+                symbolic,
+                format: args.format,
+            };
+            diff::diff(&smf, &other, opts)?
+        }
+        CliCommand::Equiv { other, realtime } => {
+            let other_bytes = std::fs::read(other)?;
+            let other = Smf::parse(&other_bytes)?;
+            let opts = equiv::Options {
+                samplerate,
+                realtime,
+                format: args.format,
+            };
+            equiv::equiv(&smf, &other, opts)?
+        }
+        CliCommand::Dump { assembly: true, .. } => asm::disassemble(&smf),
+        CliCommand::Dump {
+            assembly: false,
+            encoding,
+            realtime,
+            symbolic,
+            tracks,
+            channels,
+            kinds,
+            start,
+            end,
+        } => {
+            let range = match start {
+                Some(start) => total_pulse_of_range(&start, &end, &tempo_map, samplerate)?,
+                None => (0, None),
+            };
+            let opts = dump::Options {
+                samplerate,
+                realtime,
+                symbolic,
+                format: args.format,
+                encoding,
+                filter: dump::Filter {
+                    tracks,
+                    channels,
+                    categories: kinds,
+                    range,
+                },
+            };
+            dump::dump(&smf, &tempo_map, opts)?
+        }
+        CliCommand::Duration { extended } => {
+            time::duration(&smf, &tempo_map, samplerate, extended, args.format)?
+        }
+        CliCommand::Filter { invert, expr } => {
+            let expr = filter::parse(&expr, &tempo_map, samplerate)?;
+            filter::filter(&smf, &expr, invert)?
+        }
+        CliCommand::FilterNote {
+            start,
+            end,
+            invert,
+            crossing,
+            tracks,
+            channels,
+            keys,
+            velocities,
+        } => {
+            let range = total_pulse_of_range(&start, &end, &tempo_map, samplerate)?;
+            let filter = manip::NoteFilter {
+                tracks,
+                channels,
+                keys,
+                velocities,
+            };
+            manip::filter_note(&smf, range, invert, crossing, &filter)?
         }
         CliCommand::LoopFind { shift } => {
             let opts = loop_find::Options {
-                samplerate: args.samplerate,
-                shift: shift.map(|pb| pb.total_pulse(&timing)).transpose()?,
+                samplerate,
+                shift: shift
+                    .map(|pb| pb.total_pulse(&tempo_map, samplerate))
+                    .transpose()?,
+                format: args.format,
             };
-            loop_find::find(&smf, opts)
+            loop_find::find(&smf, &tempo_map, opts)
         }?,
         CliCommand::LoopUnfold { start } => {
-            manip::loop_unfold(&mut smf, start.total_pulse(&timing)?)?
+            manip::loop_unfold(&mut smf, start.total_pulse(&tempo_map, samplerate)?)?
+        }
+        CliCommand::Notes { pedals } => {
+            notes::notes(&smf, &tempo_map, samplerate, pedals, args.format)?
         }
         CliCommand::Smf0 => smf::smf0(&smf)?,
+        CliCommand::State { pos } => state::state(&smf, &tempo_map, &pos, samplerate, args.format)?,
+        CliCommand::TempoMap => time::tempo_map(&smf, &tempo_map, samplerate, args.format)?,
     }
     Ok(())
 }
@@ -1,10 +1,13 @@
 //! MIDI sequence manipulation.
 
-use std::{error::Error, io};
+use std::{cmp::max, collections::HashSet, error::Error, io};
 
-use midly::{num::u28, MetaMessage, Smf, TrackEvent, TrackEventKind};
+use midly::{num::u28, num::u7, MetaMessage, MidiMessage, Smf, TrackEvent, TrackEventKind};
 
-use crate::{event, time};
+use crate::{event, notes, state::MidiState, time};
+
+/// Bank Select MSB and LSB controllers.
+const BANK_SELECT: [u8; 2] = [0, 32];
 
 fn ends_with_end_of_track_event(track: &[TrackEvent]) -> bool {
     track
@@ -28,64 +31,337 @@ fn find_event_at_or_after(pulse: u64, track: &[TrackEvent]) -> Option<usize> {
     })
 }
 
-pub fn cut(smf: &mut Smf, range: (u64, Option<u64>)) -> Result<(), Box<dyn Error>> {
-    time::validate_pulse_range(smf, range)?;
+/// Events that keep the playback state of a track intact when removing `track[start..end]`.
+struct Chase<'a> {
+    /// *Note Off* events for all notes that are still sounding at the start of the cut.
+    note_offs: Vec<TrackEventKind<'a>>,
+
+    /// Controller, program, pitch bend, and tempo changes that restore the state at the end of the
+    /// cut.
+    state: Vec<TrackEventKind<'a>>,
+
+    /// Indices of the *Note Off* events after the cut that would release notes that were started
+    /// before its end.
+    stray_note_offs: Vec<usize>,
+}
+
+/// Updates the number of sounding notes per channel and key.
+fn count_note(sounding: &mut [u32], ev: &TrackEvent) {
+    if let Some(note) = event::note_on(ev) {
+        sounding[(note.channel.as_int() as usize * 128) + note.key.as_int() as usize] += 1;
+    } else if let Some(note) = event::note_off(ev) {
+        let count =
+            &mut sounding[(note.channel.as_int() as usize * 128) + note.key.as_int() as usize];
+        *count = count.saturating_sub(1);
+    }
+}
+
+fn chase<'a>(track: &[TrackEvent<'a>], start: usize, end: usize) -> Chase<'a> {
+    let mut sounding = vec![0_u32; 16 * 128];
+
+    let mut before = MidiState::new();
+    for ev in &track[..start] {
+        before.update(ev);
+        count_note(&mut sounding, ev);
+    }
+    let mut note_offs = vec![];
+    for (slot, count) in sounding.iter().enumerate() {
+        for _ in 0..*count {
+            note_offs.push(TrackEventKind::Midi {
+                channel: ((slot / 128) as u8).into(),
+                message: MidiMessage::NoteOff {
+                    key: ((slot % 128) as u8).into(),
+                    vel: 64.into(),
+                },
+            });
+        }
+    }
+
+    let mut after = before.clone();
+    for ev in &track[start..end] {
+        after.update(ev);
+        count_note(&mut sounding, ev);
+    }
+    let mut state = vec![];
+    for (channel, (before, after)) in before.ch.iter().zip(after.ch.iter()).enumerate() {
+        let channel = (channel as u8).into();
+        let mut push = |message| state.push(TrackEventKind::Midi { channel, message });
+        let (banks, controllers): (Vec<_>, Vec<_>) = (0..128_u8)
+            .map(u7::from)
+            .filter(|controller| {
+                let i = controller.as_int() as usize;
+                (before.cc[i] != after.cc[i]) && !event::is_controller_action(*controller)
+            })
+            .partition(|controller| BANK_SELECT.contains(&controller.as_int()));
+        let cc = |controller: u7| MidiMessage::Controller {
+            controller,
+            value: after.cc[controller.as_int() as usize],
+        };
+
+        // Bank Select only takes effect with the next program change, so it must come first.
+        for controller in banks {
+            push(cc(controller));
+        }
+        if before.program != after.program {
+            push(MidiMessage::ProgramChange {
+                program: after.program,
+            });
+        }
+        for controller in controllers {
+            push(cc(controller));
+        }
+        if before.bend != after.bend {
+            push(MidiMessage::PitchBend { bend: after.bend });
+        }
+    }
+    if before.tempo != after.tempo {
+        state.push(TrackEventKind::Meta(MetaMessage::Tempo(after.tempo)));
+    }
+
+    // Notes that were started before the end of the cut are released at its start, which leaves
+    // their original *Note Off* events without a note. Any new note for the same key resumes
+    // regular pairing.
+    let mut stray_note_offs = vec![];
+    for (i, ev) in track.iter().enumerate().skip(end) {
+        if let Some(note) = event::note_on(ev) {
+            sounding[(note.channel.as_int() as usize * 128) + note.key.as_int() as usize] = 0;
+        } else if let Some(note) = event::note_off(ev) {
+            let count =
+                &mut sounding[(note.channel.as_int() as usize * 128) + note.key.as_int() as usize];
+            if *count > 0 {
+                *count -= 1;
+                stray_note_offs.push(i);
+            }
+        }
+    }
+    Chase {
+        note_offs,
+        state,
+        stray_note_offs,
+    }
+}
+
+pub fn cut(
+    smf: &mut Smf,
+    range: (u64, Option<u64>),
+    chase_state: bool,
+) -> Result<(), Box<dyn Error>> {
+    let range = time::validate_pulse_range(smf, range)?;
+    let cut_len = range.end - range.start;
 
     for (track_i, track) in &mut smf.tracks.iter_mut().enumerate() {
-        let Some(start) = find_event_at_or_after(range.0, track) else {
+        let Some(start) = find_event_at_or_after(range.start, track) else {
             continue;
         };
-        let end = range
-            .1
-            .and_then(|p| find_event_at_or_after(p, track))
-            .unwrap_or(end_of_track_index(track));
+        let end = find_event_at_or_after(range.end, track).unwrap_or(end_of_track_index(track));
+        let pulse_before: u64 = track[..start]
+            .iter()
+            .map(|ev| ev.delta.as_int() as u64)
+            .sum();
+        let pulse_after: Option<u64> = track.get(end).map(|_| {
+            track[..=end]
+                .iter()
+                .map(|ev| ev.delta.as_int() as u64)
+                .sum()
+        });
         eprintln!("Track #{track_i}: Removing events #[{start}, {end}[",);
-        let start_delta = track[start].delta;
+        let mut inserted = vec![];
+        let mut stray_note_offs = vec![];
+        if chase_state {
+            let chase = chase(track, start, end);
+            inserted.extend(chase.note_offs);
+            inserted.extend(chase.state);
+            stray_note_offs = chase.stray_note_offs;
+            eprintln!(
+                "Track #{track_i}: Inserting {} events to restore the playback state, removing {} \
+                unpaired Note Off events",
+                inserted.len(),
+                stray_note_offs.len()
+            );
+        }
         track.drain(start..end);
 
-        if !ends_with_end_of_track_event(track) {
-            track.push(TrackEvent {
-                delta: start_delta,
+        // Inserted events go at the start of the range. Events after the range move earlier by its
+        // length, except for an *End of Track* event within the range, which moves to its start.
+        let mut pulse = pulse_before;
+        let mut cursor = start;
+        for kind in inserted {
+            let delta = u28::new((range.start - pulse) as u32);
+            track.insert(cursor, TrackEvent { delta, kind });
+            pulse = range.start;
+            cursor += 1;
+        }
+        match pulse_after {
+            Some(pulse_after) => {
+                let pulse_new = max(pulse_after.saturating_sub(cut_len), range.start);
+                track[cursor].delta = u28::new((pulse_new - pulse) as u32);
+            }
+            None => track.push(TrackEvent {
+                delta: u28::new((range.start - pulse) as u32),
                 kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
-            })
-        } else {
-            track[start].delta = start_delta;
+            }),
+        }
+
+        for i in stray_note_offs.into_iter().rev() {
+            let i = i - end + cursor;
+            let ev = track.remove(i);
+            if let Some(next) = track.get_mut(i) {
+                next.delta += ev.delta;
+            }
         }
     }
     Ok(smf.write_std(io::stdout())?)
 }
 
+/// Inserts an event at the given pulse, before any other events at the same pulse. Events past
+/// the *End of Track* event are inserted right before it.
+fn insert_at_pulse<'a>(track: &mut Vec<TrackEvent<'a>>, pulse: u64, kind: TrackEventKind<'a>) {
+    let end_of_track = end_of_track_index(track);
+    let mut pulse_cur: u64 = 0;
+    for i in 0..track.len() {
+        let pulse_next = pulse_cur + track[i].delta.as_int() as u64;
+        if pulse_next >= pulse || i == end_of_track {
+            let pulse = pulse.min(pulse_next);
+            track[i].delta = u28::new((pulse_next - pulse) as u32);
+            let delta = u28::new((pulse - pulse_cur) as u32);
+            track.insert(i, TrackEvent { delta, kind });
+            return;
+        }
+        pulse_cur = pulse_next;
+    }
+    let delta = u28::new((pulse - pulse_cur) as u32);
+    track.push(TrackEvent { delta, kind });
+}
+
+/// Returns the track without the events for which `remove` returns true, carrying their delta
+/// times over to the next retained event. `remove` receives the index and the total pulse of each
+/// event.
+pub fn remove_events<'a>(
+    track: &[TrackEvent<'a>],
+    mut remove: impl FnMut(usize, u64, &TrackEvent<'a>) -> bool,
+) -> Vec<TrackEvent<'a>> {
+    let mut pulse: u64 = 0;
+    let mut delta_carry: u28 = 0.into();
+    track
+        .iter()
+        .enumerate()
+        .filter_map(|(ev_i, ev)| {
+            let mut ev = *ev;
+            pulse += ev.delta.as_int() as u64;
+            ev.delta += delta_carry;
+            delta_carry = 0.into();
+            if remove(ev_i, pulse, &ev) {
+                delta_carry = ev.delta;
+                return None;
+            }
+            Some(ev)
+        })
+        .collect()
+}
+
+/// How `filter-note` treats notes that are only partially within the filtered region.
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Crossing {
+    /// Filter notes according to the position of their *Note On* event
+    #[default]
+    Start,
+
+    /// Like `start`, but cut off notes that sound into the region with a *Note Off* event at its
+    /// boundary
+    Truncate,
+
+    /// Only filter notes that are completely within the region
+    Keep,
+}
+
+/// Note properties that `filter-note` selects notes by, in addition to the range. Empty lists
+/// match all notes.
+pub struct NoteFilter {
+    /// 0-based track numbers.
+    pub tracks: Vec<usize>,
+
+    /// 1-based MIDI channels.
+    pub channels: Vec<u8>,
+
+    pub keys: Vec<event::KeyRange>,
+
+    /// Ranges of *Note On* velocities.
+    pub velocities: Vec<event::ValueRange>,
+}
+
+impl NoteFilter {
+    fn matches(&self, note: &notes::Note) -> bool {
+        (self.tracks.is_empty() || self.tracks.contains(&note.track))
+            && (self.channels.is_empty() || self.channels.contains(&(note.channel.as_int() + 1)))
+            && (self.keys.is_empty() || self.keys.iter().any(|r| r.0.contains(&note.key)))
+            && (self.velocities.is_empty()
+                || self.velocities.iter().any(|r| r.0.contains(&note.vel)))
+    }
+}
+
 pub fn filter_note(
     smf: &Smf,
     range: (u64, Option<u64>),
     invert: bool,
+    crossing: Crossing,
+    filter: &NoteFilter,
 ) -> Result<(), Box<dyn Error>> {
     let range = time::validate_pulse_range(smf, range)?;
 
+    // The filtered region is either the range, or everything outside of it.
+    let in_region = |pulse: u64| range.contains(&pulse) ^ invert;
+    let region_start_after = |pulse: u64| match invert {
+        false => (pulse < range.start).then_some(range.start),
+        true => range.contains(&pulse).then_some(range.end),
+    };
+    let region_end_after = |pulse: u64| match invert {
+        false => Some(range.end),
+        true => (pulse < range.start).then_some(range.start),
+    };
+
+    let mut removed = HashSet::new();
+    let mut note_offs = vec![];
+    for note in notes::pair(smf, false) {
+        if !filter.matches(&note) {
+            if invert {
+                removed.insert((note.track, note.event));
+                removed.extend(note.release_event);
+            }
+            continue;
+        }
+        if in_region(note.start) {
+            let crosses_end = region_end_after(note.start).is_some_and(|end| note.end > end);
+            if crossing == Crossing::Keep && crosses_end {
+                continue;
+            }
+            removed.insert((note.track, note.event));
+            removed.extend(note.release_event);
+        } else if crossing == Crossing::Truncate {
+            let Some(start) = region_start_after(note.start).filter(|start| note.end > *start)
+            else {
+                continue;
+            };
+            removed.extend(note.release_event);
+            let message = MidiMessage::NoteOff {
+                key: note.key,
+                vel: 64.into(),
+            };
+            let channel = note.channel;
+            note_offs.push((note.track, start, TrackEventKind::Midi { channel, message }));
+        }
+    }
+
     let mut filtered_smf = Smf {
         header: smf.header,
         tracks: vec![],
     };
 
-    for track in &smf.tracks {
-        let mut pulse: u64 = 0;
-        let mut delta_carry: u28 = 0.into();
-        filtered_smf.tracks.push(
-            track
-                .iter()
-                .filter_map(|ev| {
-                    let mut ev = *ev;
-                    pulse += ev.delta.as_int() as u64;
-                    ev.delta += delta_carry;
-                    delta_carry = 0.into();
-                    if (range.contains(&pulse) ^ invert) && event::note_on(&ev).is_some() {
-                        delta_carry = ev.delta;
-                        return None;
-                    }
-                    Some(ev)
-                })
-                .collect::<Vec<TrackEvent>>(),
-        );
+    for (track_i, track) in smf.tracks.iter().enumerate() {
+        let track = remove_events(track, |ev_i, _, _| removed.contains(&(track_i, ev_i)));
+        filtered_smf.tracks.push(track);
+    }
+    for (track_i, pulse, kind) in note_offs {
+        insert_at_pulse(&mut filtered_smf.tracks[track_i], pulse, kind);
     }
 
     Ok(filtered_smf.write_std(io::stdout())?)
@@ -113,3 +389,68 @@ pub fn loop_unfold(smf: &mut Smf, start: u64) -> Result<(), Box<dyn Error>> {
     }
     Ok(smf.write_std(io::stdout())?)
 }
+
+#[cfg(test)]
+mod tests {
+    use midly::PitchBend;
+
+    use super::*;
+
+    fn cc(controller: u8, value: u8) -> MidiMessage {
+        MidiMessage::Controller {
+            controller: controller.into(),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn chase_order() {
+        let messages = [
+            cc(7, 100),
+            MidiMessage::PitchBend {
+                bend: PitchBend(0x3000.into()),
+            },
+            MidiMessage::ProgramChange { program: 5.into() },
+            cc(10, 32),
+            cc(32, 2),
+            cc(0, 1),
+        ];
+        let mut track: Vec<_> = messages
+            .iter()
+            .map(|message| TrackEvent {
+                delta: 10.into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: *message,
+                },
+            })
+            .collect();
+        track.push(TrackEvent {
+            delta: 10.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        // Bank Select must precede the program change, which must precede all other controllers.
+        let state: Vec<_> = chase(&track, 0, messages.len())
+            .state
+            .into_iter()
+            .map(|kind| match kind {
+                TrackEventKind::Midi { message, .. } => message,
+                _ => panic!("unexpected event: {kind:?}"),
+            })
+            .collect();
+        assert_eq!(
+            state,
+            [
+                cc(0, 1),
+                cc(32, 2),
+                MidiMessage::ProgramChange { program: 5.into() },
+                cc(7, 100),
+                cc(10, 32),
+                MidiMessage::PitchBend {
+                    bend: PitchBend(0x3000.into()),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Symbolic names for MIDI values.
+
+use midly::num::{u4, u7};
+
+/// 0-based index of the General MIDI percussion channel, which is channel 10 in 1-based terms.
+pub const GM_PERCUSSION_CHANNEL: u8 = 9;
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Returns the scientific pitch name of a key, with key 60 being `C4`.
+pub fn note(key: u7) -> String {
+    let key = key.as_int() as i32;
+    format!("{}{}", PITCH_CLASSES[(key % 12) as usize], (key / 12) - 1)
+}
+
+/// Returns the name of a key on the given 0-based channel: the drum name followed by the pitch
+/// name on the General MIDI percussion channel, and the pitch name on all other channels.
+pub fn key(key: u7, channel: u4) -> String {
+    match gm_drum(key).filter(|_| channel.as_int() == GM_PERCUSSION_CHANNEL) {
+        Some(drum) => format!("{drum} ({})", note(key)),
+        None => note(key),
+    }
+}
+
+/// Parses a scientific pitch name as returned by `note()`, with optional `#` or `b` accidentals.
+pub fn parse_note(s: &str) -> Option<u7> {
+    let mut chars = s.chars();
+    let pitch_class: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let octave: i32 = octave.parse().ok()?;
+    let key = ((octave + 1) * 12) + pitch_class + accidental;
+    u8::try_from(key).ok().and_then(u7::try_from)
+}
+
+/// Returns the name of a key on the General MIDI percussion channel.
+pub fn gm_drum(key: u7) -> Option<&'static str> {
+    const GM_DRUMS: [&str; 47] = [
+        "Acoustic Bass Drum",
+        "Bass Drum 1",
+        "Side Stick",
+        "Acoustic Snare",
+        "Hand Clap",
+        "Electric Snare",
+        "Low Floor Tom",
+        "Closed Hi-Hat",
+        "High Floor Tom",
+        "Pedal Hi-Hat",
+        "Low Tom",
+        "Open Hi-Hat",
+        "Low-Mid Tom",
+        "Hi-Mid Tom",
+        "Crash Cymbal 1",
+        "High Tom",
+        "Ride Cymbal 1",
+        "Chinese Cymbal",
+        "Ride Bell",
+        "Tambourine",
+        "Splash Cymbal",
+        "Cowbell",
+        "Crash Cymbal 2",
+        "Vibraslap",
+        "Ride Cymbal 2",
+        "Hi Bongo",
+        "Low Bongo",
+        "Mute Hi Conga",
+        "Open Hi Conga",
+        "Low Conga",
+        "High Timbale",
+        "Low Timbale",
+        "High Agogo",
+        "Low Agogo",
+        "Cabasa",
+        "Maracas",
+        "Short Whistle",
+        "Long Whistle",
+        "Short Guiro",
+        "Long Guiro",
+        "Claves",
+        "Hi Wood Block",
+        "Low Wood Block",
+        "Mute Cuica",
+        "Open Cuica",
+        "Mute Triangle",
+        "Open Triangle",
+    ];
+    let i = (key.as_int() as usize).checked_sub(35)?;
+    GM_DRUMS.get(i).copied()
+}
+
+/// Returns the name of a General MIDI instrument.
+pub fn gm_program(program: u7) -> &'static str {
+    const GM_PROGRAMS: [&str; 128] = [
+        // Piano
+        "Acoustic Grand Piano",
+        "Bright Acoustic Piano",
+        "Electric Grand Piano",
+        "Honky-tonk Piano",
+        "Electric Piano 1",
+        "Electric Piano 2",
+        "Harpsichord",
+        "Clavi",
+        // Chromatic Percussion
+        "Celesta",
+        "Glockenspiel",
+        "Music Box",
+        "Vibraphone",
+        "Marimba",
+        "Xylophone",
+        "Tubular Bells",
+        "Dulcimer",
+        // Organ
+        "Drawbar Organ",
+        "Percussive Organ",
+        "Rock Organ",
+        "Church Organ",
+        "Reed Organ",
+        "Accordion",
+        "Harmonica",
+        "Tango Accordion",
+        // Guitar
+        "Acoustic Guitar (nylon)",
+        "Acoustic Guitar (steel)",
+        "Electric Guitar (jazz)",
+        "Electric Guitar (clean)",
+        "Electric Guitar (muted)",
+        "Overdriven Guitar",
+        "Distortion Guitar",
+        "Guitar Harmonics",
+        // Bass
+        "Acoustic Bass",
+        "Electric Bass (finger)",
+        "Electric Bass (pick)",
+        "Fretless Bass",
+        "Slap Bass 1",
+        "Slap Bass 2",
+        "Synth Bass 1",
+        "Synth Bass 2",
+        // Strings
+        "Violin",
+        "Viola",
+        "Cello",
+        "Contrabass",
+        "Tremolo Strings",
+        "Pizzicato Strings",
+        "Orchestral Harp",
+        "Timpani",
+        // Ensemble
+        "String Ensemble 1",
+        "String Ensemble 2",
+        "SynthStrings 1",
+        "SynthStrings 2",
+        "Choir Aahs",
+        "Voice Oohs",
+        "Synth Voice",
+        "Orchestra Hit",
+        // Brass
+        "Trumpet",
+        "Trombone",
+        "Tuba",
+        "Muted Trumpet",
+        "French Horn",
+        "Brass Section",
+        "SynthBrass 1",
+        "SynthBrass 2",
+        // Reed
+        "Soprano Sax",
+        "Alto Sax",
+        "Tenor Sax",
+        "Baritone Sax",
+        "Oboe",
+        "English Horn",
+        "Bassoon",
+        "Clarinet",
+        // Pipe
+        "Piccolo",
+        "Flute",
+        "Recorder",
+        "Pan Flute",
+        "Blown Bottle",
+        "Shakuhachi",
+        "Whistle",
+        "Ocarina",
+        // Synth Lead
+        "Lead 1 (square)",
+        "Lead 2 (sawtooth)",
+        "Lead 3 (calliope)",
+        "Lead 4 (chiff)",
+        "Lead 5 (charang)",
+        "Lead 6 (voice)",
+        "Lead 7 (fifths)",
+        "Lead 8 (bass + lead)",
+        // Synth Pad
+        "Pad 1 (new age)",
+        "Pad 2 (warm)",
+        "Pad 3 (polysynth)",
+        "Pad 4 (choir)",
+        "Pad 5 (bowed)",
+        "Pad 6 (metallic)",
+        "Pad 7 (halo)",
+        "Pad 8 (sweep)",
+        // Synth Effects
+        "FX 1 (rain)",
+        "FX 2 (soundtrack)",
+        "FX 3 (crystal)",
+        "FX 4 (atmosphere)",
+        "FX 5 (brightness)",
+        "FX 6 (goblins)",
+        "FX 7 (echoes)",
+        "FX 8 (sci-fi)",
+        // Ethnic
+        "Sitar",
+        "Banjo",
+        "Shamisen",
+        "Koto",
+        "Kalimba",
+        "Bag pipe",
+        "Fiddle",
+        "Shanai",
+        // Percussive
+        "Tinkle Bell",
+        "Agogo",
+        "Steel Drums",
+        "Woodblock",
+        "Taiko Drum",
+        "Melodic Tom",
+        "Synth Drum",
+        "Reverse Cymbal",
+        // Sound Effects
+        "Guitar Fret Noise",
+        "Breath Noise",
+        "Seashore",
+        "Bird Tweet",
+        "Telephone Ring",
+        "Helicopter",
+        "Applause",
+        "Gunshot",
+    ];
+    GM_PROGRAMS[program.as_int() as usize]
+}
+
+/// Returns the name of a Roland GS drum kit on the percussion channel.
+pub fn gs_drum_kit(program: u7) -> Option<&'static str> {
+    Some(match program.as_int() {
+        0 => "Standard Kit",
+        8 => "Room Kit",
+        16 => "Power Kit",
+        24 => "Electronic Kit",
+        25 => "TR-808 Kit",
+        32 => "Jazz Kit",
+        40 => "Brush Kit",
+        48 => "Orchestra Kit",
+        56 => "SFX Kit",
+        127 => "CM-64/CM-32L Kit",
+        _ => return None,
+    })
+}
+
+/// Returns the standard name of a controller.
+pub fn controller(controller: u7) -> Option<&'static str> {
+    Some(match controller.as_int() {
+        0 => "Bank Select",
+        1 => "Modulation",
+        2 => "Breath Controller",
+        4 => "Foot Controller",
+        5 => "Portamento Time",
+        6 => "Data Entry",
+        7 => "Volume",
+        8 => "Balance",
+        10 => "Pan",
+        11 => "Expression",
+        12 => "Effect Control 1",
+        13 => "Effect Control 2",
+        16 => "General Purpose 1",
+        17 => "General Purpose 2",
+        18 => "General Purpose 3",
+        19 => "General Purpose 4",
+        32 => "Bank Select LSB",
+        33 => "Modulation LSB",
+        34 => "Breath Controller LSB",
+        36 => "Foot Controller LSB",
+        37 => "Portamento Time LSB",
+        38 => "Data Entry LSB",
+        39 => "Volume LSB",
+        40 => "Balance LSB",
+        42 => "Pan LSB",
+        43 => "Expression LSB",
+        64 => "Sustain",
+        65 => "Portamento",
+        66 => "Sostenuto",
+        67 => "Soft Pedal",
+        68 => "Legato Footswitch",
+        69 => "Hold 2",
+        70 => "Sound Variation",
+        71 => "Resonance",
+        72 => "Release Time",
+        73 => "Attack Time",
+        74 => "Brightness",
+        75 => "Decay Time",
+        76 => "Vibrato Rate",
+        77 => "Vibrato Depth",
+        78 => "Vibrato Delay",
+        80 => "General Purpose 5",
+        81 => "General Purpose 6",
+        82 => "General Purpose 7",
+        83 => "General Purpose 8",
+        84 => "Portamento Control",
+        91 => "Reverb",
+        92 => "Tremolo",
+        93 => "Chorus",
+        94 => "Detune",
+        95 => "Phaser",
+        96 => "Data Increment",
+        97 => "Data Decrement",
+        98 => "NRPN LSB",
+        99 => "NRPN MSB",
+        100 => "RPN LSB",
+        101 => "RPN MSB",
+        120 => "All Sound Off",
+        121 => "Reset All Controllers",
+        122 => "Local Control",
+        123 => "All Notes Off",
+        124 => "Omni Off",
+        125 => "Omni On",
+        126 => "Mono On",
+        127 => "Poly On",
+        _ => return None,
+    })
+}
@@ -0,0 +1,552 @@
+//! Note pairing.
+
+use std::{cmp::max, collections::VecDeque, error::Error};
+
+use midly::{
+    num::{u4, u7},
+    Smf, Timing,
+};
+use serde::Serialize;
+
+use crate::{
+    event, names,
+    output::{self, Format},
+    state::MidiState,
+    time::{MidiTimeDisplay, TempoMap, TimeRecord},
+};
+
+/// A *Note On* event paired with the event that released it.
+pub struct Note {
+    pub track: usize,
+    pub event: usize,
+    pub channel: u4,
+    pub key: u7,
+    pub vel: u7,
+    pub start: u64,
+
+    /// Pulse of the releasing event, or the end of the sequence if the note is never released.
+    pub end: u64,
+
+    /// `None` for notes released by *Note On* events with zero velocity, or never released at all.
+    pub release_vel: Option<u7>,
+    pub released: bool,
+
+    /// Track and event index of the releasing event.
+    pub release_event: Option<(usize, usize)>,
+
+    /// Another *Note On* event for the same channel and key occurred while the note was sounding.
+    pub retriggered: bool,
+
+    /// The note was held beyond its release by a sustain or sostenuto pedal.
+    pub sustained: bool,
+}
+
+const SUSTAIN: usize = 64;
+const SOSTENUTO: usize = 66;
+
+fn pedal_down(state: &MidiState, channel: usize, controller: usize) -> bool {
+    state.ch[channel].cc[controller] >= 64
+}
+
+/// Pairs every *Note On* event of the sequence with the next *Note Off* event for the same channel
+/// and key, across all tracks. Multiple sounding notes for the same channel and key are released
+/// in the order they were started. The returned notes are sorted by their start position, with
+/// simultaneous notes in track order.
+///
+/// With `pedals`, notes released while the sustain pedal (CC64) is down on their channel keep
+/// sounding until the pedal is released. The same applies to notes that were sounding when the
+/// sostenuto pedal (CC66) was pressed, until that pedal is released. Striking the same key again
+/// ends a held note.
+pub fn pair(smf: &Smf, pedals: bool) -> Vec<Note> {
+    let mut events = vec![];
+    for (track_i, track) in smf.tracks.iter().enumerate() {
+        let mut pulse = 0;
+        for (ev_i, ev) in track.iter().enumerate() {
+            pulse += ev.delta.as_int() as u64;
+            let is_pedal = event::controller(ev).is_some_and(|cc| {
+                [SUSTAIN, SOSTENUTO].contains(&(cc.controller.as_int() as usize))
+            });
+            if event::note(ev).is_some() || event::note_off(ev).is_some() || (pedals && is_pedal) {
+                events.push((pulse, track_i, ev_i, ev));
+            }
+        }
+    }
+
+    // The sort is stable, which preserves the track order of simultaneous events.
+    events.sort_by_key(|(pulse, _, _, _)| *pulse);
+
+    let end = smf
+        .tracks
+        .iter()
+        .map(|track| track.iter().map(|ev| ev.delta.as_int() as u64).sum())
+        .max()
+        .unwrap_or(0);
+
+    let mut notes: Vec<Note> = vec![];
+    let mut sounding: Vec<VecDeque<usize>> = (0..(16 * 128)).map(|_| VecDeque::new()).collect();
+
+    // Pedal state, released notes held by a pedal, and notes caught by the sostenuto pedal, per
+    // channel.
+    let mut state = MidiState::new();
+    let mut held: [Vec<usize>; 16] = Default::default();
+    let mut caught: [Vec<usize>; 16] = Default::default();
+
+    for (pulse, track, ev_i, ev) in events {
+        if let Some(cc) = event::controller(ev) {
+            let ch = cc.channel.as_int() as usize;
+            let controller = cc.controller.as_int() as usize;
+            let was_down = pedal_down(&state, ch, controller);
+            state.update(ev);
+            let is_down = pedal_down(&state, ch, controller);
+            if controller == SOSTENUTO && !was_down && is_down {
+                caught[ch] = sounding[(ch * 128)..((ch + 1) * 128)]
+                    .iter()
+                    .flatten()
+                    .chain(held[ch].iter())
+                    .copied()
+                    .collect();
+            } else if controller == SOSTENUTO && was_down && !is_down {
+                caught[ch].clear();
+            }
+            if was_down && !is_down {
+                let sustain = pedal_down(&state, ch, SUSTAIN);
+                held[ch].retain(|note_i| {
+                    if sustain || caught[ch].contains(note_i) {
+                        return true;
+                    }
+                    notes[*note_i].end = pulse;
+                    false
+                });
+            }
+        } else if let Some(on) = event::note_on(ev) {
+            let ch = on.channel.as_int() as usize;
+            held[ch].retain(|note_i| {
+                if notes[*note_i].key != on.key {
+                    return true;
+                }
+                notes[*note_i].end = pulse;
+                false
+            });
+            let slot = &mut sounding[(ch * 128) + on.key.as_int() as usize];
+            for note_i in slot.iter() {
+                notes[*note_i].retriggered = true;
+            }
+            slot.push_back(notes.len());
+            notes.push(Note {
+                track,
+                event: ev_i,
+                channel: on.channel,
+                key: on.key,
+                vel: on.vel,
+                start: pulse,
+                end,
+                release_vel: None,
+                released: false,
+                release_event: None,
+                retriggered: false,
+                sustained: false,
+            });
+        } else if let Some(off) = event::note_off(ev) {
+            let ch = off.channel.as_int() as usize;
+            let slot = &mut sounding[(ch * 128) + off.key.as_int() as usize];
+            if let Some(note_i) = slot.pop_front() {
+                let note = &mut notes[note_i];
+                note.released = true;
+                note.release_event = Some((track, ev_i));
+                note.release_vel = event::note(ev).is_none().then_some(off.vel);
+                if pedal_down(&state, ch, SUSTAIN)
+                    || (pedal_down(&state, ch, SOSTENUTO) && caught[ch].contains(&note_i))
+                {
+                    note.sustained = true;
+                    held[ch].push(note_i);
+                } else {
+                    note.end = pulse;
+                }
+            }
+        }
+    }
+    notes
+}
+
+#[derive(Serialize)]
+struct NoteRecord {
+    track: usize,
+    event: usize,
+    channel: u8,
+    key: u8,
+    key_name: String,
+    vel: u8,
+    release_vel: Option<u8>,
+    start: TimeRecord,
+    end: TimeRecord,
+    length_pulses: u64,
+    length_realtime_us: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length_samples: Option<u64>,
+    released: bool,
+    retriggered: bool,
+    sustained: bool,
+}
+
+impl Note {
+    pub fn key_name(&self) -> String {
+        names::key(self.key, self.channel)
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = vec![];
+        if !self.released {
+            flags.push("unreleased");
+        }
+        if self.retriggered {
+            flags.push("retriggered");
+        }
+        if self.sustained {
+            flags.push("sustained");
+        }
+        flags.join(", ")
+    }
+}
+
+pub fn notes(
+    smf: &Smf,
+    tempo_map: &TempoMap,
+    samplerate: Option<u32>,
+    pedals: bool,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let notes = pair(smf, pedals);
+    let mut time = MidiTimeDisplay::new_at_end(tempo_map, smf, samplerate);
+
+    if format == Format::Json {
+        let notes = notes
+            .iter()
+            .map(|note| {
+                let start = tempo_map.time_at(note.start);
+                let end = tempo_map.time_at(note.end);
+                time.time = start;
+                let start_record = time.record();
+                time.time = end;
+                let end_record = time.record();
+                NoteRecord {
+                    track: note.track,
+                    event: note.event,
+                    channel: note.channel.as_int() + 1,
+                    key: note.key.as_int(),
+                    key_name: note.key_name(),
+                    vel: note.vel.as_int(),
+                    release_vel: note.release_vel.map(|vel| vel.as_int()),
+                    start: start_record,
+                    end: end_record,
+                    length_pulses: note.end - note.start,
+                    length_realtime_us: (end.realtime() - start.realtime()).as_micros_f64(),
+                    length_samples: samplerate.map(|r| end.sample(r) - start.sample(r)),
+                    released: note.released,
+                    retriggered: note.retriggered,
+                    sustained: note.sustained,
+                }
+            })
+            .collect::<Vec<_>>();
+        #[derive(Serialize)]
+        struct Output {
+            notes: Vec<NoteRecord>,
+        }
+        return Ok(output::print_json(&Output { notes })?);
+    }
+
+    let widths = time.widths();
+    let track_header = "Track";
+    let event_header = "Event";
+    let pulse_header = "Pulse";
+    let beat_header = time.beat_unit();
+    let beat_header = &format!("{}{}", beat_header[..1].to_uppercase(), &beat_header[1..]);
+    let bar_header = "Bar";
+    let realtime_header = "Realtime";
+    let sample_header = "Sample";
+    let length_header = "Length";
+    let length_beat_header = "Length (beat)";
+    let length_realtime_header = "Length (realtime)";
+    let length_sample_header = "Length (samples)";
+    let track_width = max(
+        track_header.len(),
+        (max(smf.tracks.len(), 1).ilog10() + 1) as usize,
+    );
+    let event_width = max(
+        event_header.len(),
+        smf.tracks
+            .iter()
+            .map(|track| (max(track.len(), 1).ilog10() + 1) as usize)
+            .max()
+            .unwrap_or(1),
+    );
+    let pulse_width = max(pulse_header.len(), widths.pulse);
+    let beat_width = max(beat_header.len(), widths.beat);
+    let bar_width = max(bar_header.len(), widths.bar);
+    let realtime_width = max(realtime_header.len(), widths.realtime);
+    let sample_width = max(sample_header.len(), widths.sample);
+    let length_width = max(length_header.len(), widths.pulse);
+    let length_beat_width = max(length_beat_header.len(), widths.beat);
+    let length_realtime_width = max(length_realtime_header.len(), widths.realtime);
+    let length_sample_width = max(length_sample_header.len(), widths.sample);
+    let has_bars = time.display_bar().is_some();
+
+    // Lengths are durations rather than positions, so they are formatted directly from their pulse
+    // count. Sequences with SMPTE timing have no beats, and drop-frame timecodes only label
+    // positions.
+    let ppqn = match smf.header.timing {
+        Timing::Metrical(ppqn) => Some(ppqn.as_int() as u64),
+        Timing::Timecode(_, _) => None,
+    };
+
+    print!("{track_header:>track_width$}\t{event_header:>event_width$}");
+    print!("\t{pulse_header:>pulse_width$}\t{beat_header:>beat_width$}");
+    if has_bars {
+        print!("\t{bar_header:>bar_width$}");
+    }
+    print!("\t{realtime_header:>realtime_width$}");
+    if samplerate.is_some() {
+        print!("\t{sample_header:>sample_width$}");
+    }
+    print!("\t{length_header:>length_width$}");
+    if ppqn.is_some() {
+        print!("\t{length_beat_header:>length_beat_width$}");
+    }
+    print!("\t{length_realtime_header:>length_realtime_width$}");
+    if samplerate.is_some() {
+        print!("\t{length_sample_header:>length_sample_width$}");
+    }
+    println!("\tCh\tKey\tVel\tOff\tNote");
+    for note in &notes {
+        let start = tempo_map.time_at(note.start);
+        let end = tempo_map.time_at(note.end);
+        time.time = start;
+        print!("{:>track_width$}\t{:>event_width$}", note.track, note.event);
+        print!(
+            "\t{:>pulse_width$}\t{:>beat_width$}",
+            time.display_pulse(),
+            time.display_beat()
+        );
+        if let Some(bar) = time.display_bar() {
+            print!("\t{bar:>bar_width$}");
+        }
+        print!("\t{:>realtime_width$}", time.display_realtime());
+        if let Some(sample) = time.display_sample() {
+            print!("\t{sample:>sample_width$}");
+        }
+        let length = note.end - note.start;
+        print!("\t{length:>length_width$}");
+        if let Some(ppqn) = ppqn {
+            let (qn_width, pulse_width) = (widths.beat_qn, widths.beat_pulse);
+            let beat = format!(
+                "{:>qn_width$}:{:>0pulse_width$}",
+                length / ppqn,
+                length % ppqn
+            );
+            print!("\t{beat:>length_beat_width$}");
+        }
+        print!(
+            "\t{:>length_realtime_width$}",
+            end.realtime() - start.realtime()
+        );
+        if let Some(samplerate) = samplerate {
+            let samples = end.sample(samplerate) - start.sample(samplerate);
+            print!("\t{samples:>length_sample_width$}");
+        }
+        let release_vel = note
+            .release_vel
+            .map(|vel| vel.to_string())
+            .unwrap_or_default();
+        print!(
+            "\t{:>2}\t{:>3}\t{:>3}\t{release_vel:>3}\t{}",
+            note.channel.as_int() + 1,
+            note.key,
+            note.vel,
+            note.key_name(),
+        );
+        let flags = note.flags();
+        if !flags.is_empty() {
+            print!(" [{flags}]");
+        }
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{Format, Header, MidiMessage, Timing, TrackEvent, TrackEventKind};
+
+    use super::*;
+
+    fn on(key: u8, vel: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        }
+    }
+
+    fn off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 64.into(),
+        }
+    }
+
+    /// Builds a single-track sequence from channel 1 messages at the given pulses.
+    fn smf(messages: &[(u32, MidiMessage)]) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(480.into()),
+        ));
+        let mut pulse = 0;
+        let mut track = vec![];
+        for (at, message) in messages {
+            track.push(TrackEvent {
+                delta: (at - pulse).into(),
+                kind: TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: *message,
+                },
+            });
+            pulse = *at;
+        }
+        track.push(TrackEvent {
+            delta: (1000 - pulse).into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(track);
+        smf
+    }
+
+    /// Returns the start and end pulse of every note.
+    fn spans(notes: &[Note]) -> Vec<(u64, u64)> {
+        notes.iter().map(|note| (note.start, note.end)).collect()
+    }
+
+    #[test]
+    fn retriggered_notes() {
+        // The first *Note Off* event releases the earliest note for the same key.
+        let notes = pair(
+            &smf(&[
+                (0, on(60, 100)),
+                (100, on(60, 90)),
+                (200, off(60)),
+                (300, off(60)),
+            ]),
+            false,
+        );
+        assert_eq!(spans(&notes), [(0, 200), (100, 300)]);
+        assert!(notes[0].retriggered && !notes[1].retriggered);
+        assert!(notes.iter().all(|note| note.released));
+        assert_eq!(notes[0].release_event, Some((0, 2)));
+        assert_eq!(notes[1].release_event, Some((0, 3)));
+    }
+
+    #[test]
+    fn zero_velocity_and_unreleased_notes() {
+        let notes = pair(
+            &smf(&[(0, on(60, 100)), (100, on(60, 0)), (200, on(62, 100))]),
+            false,
+        );
+        assert_eq!(spans(&notes), [(0, 100), (200, 1000)]);
+        assert_eq!(notes[0].release_vel, None);
+        assert!(notes[0].released && !notes[1].released);
+
+        // Unpaired *Note Off* events are ignored.
+        let notes = pair(
+            &smf(&[(0, off(60)), (100, on(60, 100)), (200, off(60))]),
+            false,
+        );
+        assert_eq!(spans(&notes), [(100, 200)]);
+        assert_eq!(notes[0].release_vel, Some(64.into()));
+    }
+
+    fn cc(controller: u8, value: u8) -> MidiMessage {
+        MidiMessage::Controller {
+            controller: controller.into(),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn sustain_pedal() {
+        let messages = [
+            (0, cc(64, 127)),
+            (0, on(60, 100)),
+            (100, off(60)),
+            (150, on(62, 100)),
+            (160, off(62)),
+            (200, cc(64, 0)),
+        ];
+        let notes = pair(&smf(&messages), true);
+        assert_eq!(spans(&notes), [(0, 200), (150, 200)]);
+        assert!(notes.iter().all(|note| note.sustained && note.released));
+
+        // Without `pedals`, the pedal is ignored.
+        let notes = pair(&smf(&messages), false);
+        assert_eq!(spans(&notes), [(0, 100), (150, 160)]);
+        assert!(notes.iter().all(|note| !note.sustained));
+
+        // Values below 64 release the pedal.
+        let notes = pair(
+            &smf(&[
+                (0, cc(64, 64)),
+                (0, on(60, 100)),
+                (100, off(60)),
+                (150, cc(64, 63)),
+            ]),
+            true,
+        );
+        assert_eq!(spans(&notes), [(0, 150)]);
+    }
+
+    #[test]
+    fn sustain_pedal_restrike() {
+        // Striking the same key again ends the held note.
+        let notes = pair(
+            &smf(&[
+                (0, cc(64, 127)),
+                (0, on(60, 100)),
+                (50, off(60)),
+                (100, on(60, 100)),
+                (150, off(60)),
+                (300, cc(64, 0)),
+            ]),
+            true,
+        );
+        assert_eq!(spans(&notes), [(0, 100), (100, 300)]);
+        assert!(!notes[0].retriggered);
+    }
+
+    #[test]
+    fn sostenuto_pedal() {
+        // Only notes that were sounding when the pedal was pressed are held.
+        let notes = pair(
+            &smf(&[
+                (0, on(60, 100)),
+                (50, cc(66, 127)),
+                (60, on(62, 100)),
+                (100, off(60)),
+                (110, off(62)),
+                (200, cc(66, 0)),
+            ]),
+            true,
+        );
+        assert_eq!(spans(&notes), [(0, 200), (60, 110)]);
+        assert!(notes[0].sustained && !notes[1].sustained);
+
+        // Notes held by both pedals last until both are released.
+        let notes = pair(
+            &smf(&[
+                (0, on(60, 100)),
+                (10, cc(66, 127)),
+                (20, cc(64, 127)),
+                (100, off(60)),
+                (200, cc(66, 0)),
+                (300, cc(64, 0)),
+            ]),
+            true,
+        );
+        assert_eq!(spans(&notes), [(0, 300)]);
+    }
+}
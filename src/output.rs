@@ -0,0 +1,21 @@
+//! Output formats for reports.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable text with aligned columns
+    #[default]
+    Text,
+
+    /// A single JSON document
+    Json,
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), io::Error> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)
+}
@@ -4,31 +4,33 @@ use std::io;
 
 use midly::{num::u28, MetaMessage, Smf, TrackEvent, TrackEventKind};
 
-pub fn smf0(smf: &Smf) -> Result<(), io::Error> {
-    if smf.tracks.len() <= 1 {
-        return smf.write_std(io::stdout());
-    }
-
-    let mut smf0 = Smf {
-        header: smf.header,
-        tracks: vec![Vec::with_capacity(
-            smf.tracks.iter().fold(0, |acc, track| acc + track.len()),
-        )],
-    };
-    smf0.header.format = midly::Format::SingleTrack;
+/// An event of a merged sequence, together with its position in the original sequence.
+pub struct MergedEvent<'a> {
+    pub pulse: u64,
+    pub track: usize,
+    pub index: usize,
+    pub ev: &'a TrackEvent<'a>,
+}
 
+/// Merges all tracks of the sequence into a single list of events. Simultaneous events are sorted
+/// according to the track order of the input sequence.
+pub fn merge<'a>(smf: &'a Smf) -> Vec<MergedEvent<'a>> {
     struct TrackMerge<'a> {
+        track_i: usize,
         track: &'a [TrackEvent<'a>],
         i: usize,
         len: usize,
         pulse_of_i: u64,
     }
 
+    let mut ret = Vec::with_capacity(smf.tracks.iter().fold(0, |acc, track| acc + track.len()));
     let mut merge_tracks = smf
         .tracks
         .iter()
-        .filter_map(|track| {
+        .enumerate()
+        .filter_map(|(track_i, track)| {
             track.first().map(|ev| TrackMerge {
+                track_i,
                 track,
                 i: 0,
                 len: track.len(),
@@ -37,31 +39,55 @@ pub fn smf0(smf: &Smf) -> Result<(), io::Error> {
         })
         .collect::<Vec<_>>();
 
-    let mut pulse: u64 = 0;
-    let mut delta_last = u28::new(0);
     while let Some((merge_i, merge)) = merge_tracks
         .iter_mut()
         .enumerate()
         .min_by(|a, b| a.1.pulse_of_i.cmp(&b.1.pulse_of_i))
     {
-        let kind = merge.track[merge.i].kind;
-        delta_last = u28::new((merge.pulse_of_i - pulse) as u32);
-        if !matches!(kind, TrackEventKind::Meta(MetaMessage::EndOfTrack)) {
-            smf0.tracks[0].push(TrackEvent {
-                delta: delta_last,
-                kind,
-            });
-        }
+        ret.push(MergedEvent {
+            pulse: merge.pulse_of_i,
+            track: merge.track_i,
+            index: merge.i,
+            ev: &merge.track[merge.i],
+        });
         merge.i += 1;
         if merge.i >= merge.len {
             merge_tracks.remove(merge_i);
             continue;
         }
-        pulse = merge.pulse_of_i;
         merge.pulse_of_i += merge.track[merge.i].delta.as_int() as u64;
     }
+    ret
+}
+
+pub fn smf0(smf: &Smf) -> Result<(), io::Error> {
+    if smf.tracks.len() <= 1 {
+        return smf.write_std(io::stdout());
+    }
+
+    let mut smf0 = Smf {
+        header: smf.header,
+        tracks: vec![Vec::with_capacity(
+            smf.tracks.iter().fold(0, |acc, track| acc + track.len()),
+        )],
+    };
+    smf0.header.format = midly::Format::SingleTrack;
+
+    let mut pulse: u64 = 0;
+    let mut pulse_end: u64 = 0;
+    for merged in merge(smf) {
+        let kind = merged.ev.kind;
+        pulse_end = merged.pulse;
+        if !matches!(kind, TrackEventKind::Meta(MetaMessage::EndOfTrack)) {
+            smf0.tracks[0].push(TrackEvent {
+                delta: u28::new((merged.pulse - pulse) as u32),
+                kind,
+            });
+            pulse = merged.pulse;
+        }
+    }
     smf0.tracks[0].push(TrackEvent {
-        delta: delta_last,
+        delta: u28::new((pulse_end - pulse) as u32),
         kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
     });
     smf0.write_std(io::stdout())
@@ -1,21 +1,42 @@
 //! Per-channel state tracking.
 
+use std::error::Error;
+
 use midly::{
-    num::{u24, u7},
-    MetaMessage, MidiMessage, PitchBend, TrackEvent, TrackEventKind,
+    num::{u24, u4, u7},
+    MetaMessage, MidiMessage, PitchBend, Smf, TrackEvent, TrackEventKind,
+};
+use serde::Serialize;
+
+use crate::{
+    dump::{Encoding, SymbolicDisplay},
+    names, notes,
+    output::{self, Format},
+    time::{MidiTimeDisplay, PulseOrBeat, TempoMap, TimeRecord},
 };
 
+/// Returns the power-on value of a controller as defined by General MIDI.
+fn default_cc(controller: usize) -> u7 {
+    match controller {
+        7 => 100.into(),
+        10 => 64.into(),
+        11 => 127.into(),
+        98..=101 => 127.into(),
+        _ => 0.into(),
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub struct MidiStateOnChannel {
     pub cc: [u7; 128],
-    program: u7,
-    bend: PitchBend,
+    pub program: u7,
+    pub bend: PitchBend,
 }
 
 #[derive(Clone, PartialEq)]
 pub struct MidiState {
     pub ch: [MidiStateOnChannel; 16],
-    tempo: u24,
+    pub tempo: u24,
 }
 
 impl MidiState {
@@ -30,6 +51,16 @@ impl MidiState {
         }
     }
 
+    /// Returns the power-on state of a General MIDI device, which the `state` command compares
+    /// against.
+    fn power_on() -> Self {
+        let mut ret = Self::new();
+        for ch in &mut ret.ch {
+            ch.cc = std::array::from_fn(default_cc);
+        }
+        ret
+    }
+
     pub fn update(&mut self, ev: &TrackEvent) {
         if let TrackEventKind::Midi { channel, message } = ev.kind {
             match message {
@@ -50,3 +81,172 @@ impl MidiState {
         }
     }
 }
+
+#[derive(Serialize)]
+struct ControllerRecord {
+    controller: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    value: u8,
+}
+
+#[derive(Serialize)]
+struct SoundingNoteRecord {
+    track: usize,
+    event: usize,
+    key: u8,
+    key_name: String,
+    vel: u8,
+    start: TimeRecord,
+}
+
+#[derive(Serialize)]
+struct ChannelRecord {
+    channel: u8,
+    program: u8,
+    bend: i16,
+    controllers: Vec<ControllerRecord>,
+    notes: Vec<SoundingNoteRecord>,
+}
+
+#[derive(Serialize)]
+struct StateRecord {
+    input: String,
+    time: TimeRecord,
+    qn_micros: u64,
+    bpm: f64,
+    channels: Vec<ChannelRecord>,
+}
+
+/// Prints the state of all channels after replaying all events up to and including the given
+/// position.
+pub fn state(
+    smf: &Smf,
+    tempo_map: &TempoMap,
+    pos: &PulseOrBeat,
+    samplerate: Option<u32>,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let pulse = pos.total_pulse(tempo_map, samplerate)?;
+    let mut time = MidiTimeDisplay::new_at_end(tempo_map, smf, samplerate);
+
+    let mut events = vec![];
+    for track in &smf.tracks {
+        let mut pulse_cur = 0;
+        for ev in track {
+            pulse_cur += ev.delta.as_int() as u64;
+            if pulse_cur > pulse {
+                break;
+            }
+            events.push((pulse_cur, ev));
+        }
+    }
+
+    // The sort is stable, which preserves the track order of simultaneous events.
+    events.sort_by_key(|(pulse, _)| *pulse);
+    let mut state = MidiState::power_on();
+    for (_, ev) in events {
+        state.update(ev);
+    }
+    let default = MidiState::power_on();
+
+    let sounding = notes::pair(smf, true)
+        .into_iter()
+        .filter(|note| note.start <= pulse && (pulse < note.end || !note.released))
+        .collect::<Vec<_>>();
+
+    let channels = (0..16)
+        .filter_map(|ch| {
+            let cur = &state.ch[ch];
+            let controllers = (0..128)
+                .filter(|cc| cur.cc[*cc] != default.ch[ch].cc[*cc])
+                .map(|cc| ControllerRecord {
+                    controller: cc as u8,
+                    name: names::controller((cc as u8).into()),
+                    value: cur.cc[cc].as_int(),
+                })
+                .collect::<Vec<_>>();
+            let notes = sounding
+                .iter()
+                .filter(|note| note.channel.as_int() as usize == ch)
+                .map(|note| {
+                    time.time = tempo_map.time_at(note.start);
+                    SoundingNoteRecord {
+                        track: note.track,
+                        event: note.event,
+                        key: note.key.as_int(),
+                        key_name: note.key_name(),
+                        vel: note.vel.as_int(),
+                        start: time.record(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            if *cur == default.ch[ch] && notes.is_empty() {
+                return None;
+            }
+            Some(ChannelRecord {
+                channel: (ch as u8) + 1,
+                program: cur.program.as_int(),
+                bend: cur.bend.as_int(),
+                controllers,
+                notes,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    time.time = tempo_map.time_at(pulse);
+    let record = StateRecord {
+        input: pos.to_string(),
+        time: time.record(),
+        qn_micros: time.time.qn_micros(),
+        bpm: time.time.bpm(),
+        channels,
+    };
+    if format == Format::Json {
+        return Ok(output::print_json(&record)?);
+    }
+
+    let (qn_micros, bpm) = (record.qn_micros, record.bpm);
+    println!("{}: {time}", record.input);
+    println!("  Tempo: {qn_micros} µs per quarter note ({bpm:.3} BPM)");
+    for ch in &record.channels {
+        let channel = u4::from(ch.channel - 1);
+        let print = |message| {
+            let kind = TrackEventKind::Midi { channel, message };
+            println!("  {}", SymbolicDisplay(&kind, Encoding::Auto));
+        };
+        let default = &default.ch[channel.as_int() as usize];
+        let cur = &state.ch[channel.as_int() as usize];
+        if cur.program != default.program {
+            print(MidiMessage::ProgramChange {
+                program: cur.program,
+            });
+        }
+        for cc in &ch.controllers {
+            print(MidiMessage::Controller {
+                controller: cc.controller.into(),
+                value: cc.value.into(),
+            });
+        }
+        if cur.bend != default.bend {
+            print(MidiMessage::PitchBend { bend: cur.bend });
+        }
+        for note in &ch.notes {
+            let kind = TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn {
+                    key: note.key.into(),
+                    vel: note.vel.into(),
+                },
+            };
+            println!(
+                "  {} (track {}, event {}, at pulse {})",
+                SymbolicDisplay(&kind, Encoding::Auto),
+                note.track,
+                note.event,
+                note.start.pulse
+            );
+        }
+    }
+    Ok(())
+}
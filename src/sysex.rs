@@ -0,0 +1,324 @@
+//! Decoding of common System Exclusive messages.
+
+const ROLAND: u8 = 0x41;
+const YAMAHA: u8 = 0x43;
+const UNIVERSAL_NON_REALTIME: u8 = 0x7E;
+const UNIVERSAL_REALTIME: u8 = 0x7F;
+
+const ROLAND_MODEL_MT32: u8 = 0x16;
+const ROLAND_MODEL_GS: u8 = 0x42;
+const ROLAND_MODEL_SC55_DISPLAY: u8 = 0x45;
+const ROLAND_COMMAND_RQ1: u8 = 0x11;
+const ROLAND_COMMAND_DT1: u8 = 0x12;
+
+const YAMAHA_MODEL_XG: u8 = 0x4C;
+
+/// Formats bytes as space-separated hexadecimal values.
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the checksum that Roland devices expect for the given address and data bytes.
+pub fn roland_checksum(bytes: &[u8]) -> u8 {
+    let sum = bytes.iter().fold(0_u8, |acc, b| acc.wrapping_add(*b)) & 0x7F;
+    (0x80 - sum) & 0x7F
+}
+
+fn universal_non_realtime(msg: &[u8]) -> Option<String> {
+    Some(match msg {
+        [0x09, 0x01] => "GM System On".into(),
+        [0x09, 0x02] => "GM System Off".into(),
+        [0x09, 0x03] => "GM2 System On".into(),
+        _ => return None,
+    })
+}
+
+fn universal_realtime(msg: &[u8]) -> Option<String> {
+    let [0x04, param, lsb, msb] = *msg else {
+        return None;
+    };
+    let value = ((msb as u16) << 7) | (lsb as u16);
+    let name = match param {
+        0x01 => "Master Volume",
+        0x02 => "Master Balance",
+        0x03 => "Master Fine Tuning",
+        0x04 => "Master Coarse Tuning",
+        _ => return None,
+    };
+    Some(format!("{name} = {value}"))
+}
+
+/// Maps the part number nibble of a GS address to the 1-based part number. Part 10 comes first.
+fn gs_part(nibble: u8) -> u8 {
+    match nibble {
+        0x0 => 10,
+        0x1..=0x9 => nibble,
+        _ => nibble + 1,
+    }
+}
+
+fn gs_parameter(addr: [u8; 3], data: &[u8]) -> Option<String> {
+    let value = *data.first()?;
+    let value_name = |names: &[&str]| names.get(value as usize).map(|s| format!(" ({s})"));
+    let (name, value_name) = match addr {
+        [0x40, 0x00, 0x7F] if value == 0x00 => return Some("Reset".into()),
+        [0x00, 0x00, 0x7F] => (
+            "System Mode Set".into(),
+            value_name(&["Mode 1, single module", "Mode 2, double module"]),
+        ),
+        [0x40, 0x00, 0x00] => ("Master Tune".into(), None),
+        [0x40, 0x00, 0x04] => ("Master Volume".into(), None),
+        [0x40, 0x00, 0x05] => ("Master Key-Shift".into(), None),
+        [0x40, 0x00, 0x06] => ("Master Pan".into(), None),
+        [0x40, 0x01, 0x30] => (
+            "Reverb Macro".into(),
+            value_name(&[
+                "Room 1",
+                "Room 2",
+                "Room 3",
+                "Hall 1",
+                "Hall 2",
+                "Plate",
+                "Delay",
+                "Panning Delay",
+            ]),
+        ),
+        [0x40, 0x01, 0x31] => ("Reverb Character".into(), None),
+        [0x40, 0x01, 0x32] => ("Reverb Pre-LPF".into(), None),
+        [0x40, 0x01, 0x33] => ("Reverb Level".into(), None),
+        [0x40, 0x01, 0x34] => ("Reverb Time".into(), None),
+        [0x40, 0x01, 0x35] => ("Reverb Delay Feedback".into(), None),
+        [0x40, 0x01, 0x38] => (
+            "Chorus Macro".into(),
+            value_name(&[
+                "Chorus 1",
+                "Chorus 2",
+                "Chorus 3",
+                "Chorus 4",
+                "Feedback Chorus",
+                "Flanger",
+                "Short Delay",
+                "Short Delay (FB)",
+            ]),
+        ),
+        [0x40, 0x01, 0x39] => ("Chorus Pre-LPF".into(), None),
+        [0x40, 0x01, 0x3A] => ("Chorus Level".into(), None),
+        [0x40, 0x01, 0x3B] => ("Chorus Feedback".into(), None),
+        [0x40, 0x01, 0x3C] => ("Chorus Delay".into(), None),
+        [0x40, 0x01, 0x3D] => ("Chorus Rate".into(), None),
+        [0x40, 0x01, 0x3E] => ("Chorus Depth".into(), None),
+        [0x40, 0x01, 0x3F] => ("Chorus Send Level to Reverb".into(), None),
+        [0x40, block, param] if (block & 0xF0) == 0x10 => {
+            let part = gs_part(block & 0x0F);
+            let (param, value_name) = match param {
+                0x00 => ("Tone Number", None),
+                0x02 => (
+                    "Rx. Channel",
+                    Some(match value {
+                        0x00..=0x0F => format!(" (channel {})", value + 1),
+                        _ => " (off)".into(),
+                    }),
+                ),
+                0x13 => ("Mono/Poly Mode", value_name(&["Mono", "Poly"])),
+                0x15 => (
+                    "Use for Rhythm Part",
+                    value_name(&["Off", "Map 1", "Map 2"]),
+                ),
+                0x16 => ("Pitch Key Shift", None),
+                0x19 => ("Part Level", None),
+                0x1A => ("Velocity Sense Depth", None),
+                0x1B => ("Velocity Sense Offset", None),
+                0x1C => ("Part Panpot", None),
+                0x1D => ("Key Range Low", None),
+                0x1E => ("Key Range High", None),
+                0x21 => ("Chorus Send Level", None),
+                0x22 => ("Reverb Send Level", None),
+                _ => return None,
+            };
+            (format!("Part {part} {param}"), value_name)
+        }
+        [0x41, block, note] if block & 0x0F <= 1 => {
+            let map = (block & 0x0F) + 1;
+            let param = match block & 0xF0 {
+                0x00 => return Some(format!("Drum Map {map} Name = {}", hex(data))),
+                0x10 => "Play Note Number",
+                0x20 => "Level",
+                0x30 => "Assign Group Number",
+                0x40 => "Panpot",
+                0x50 => "Reverb Send Level",
+                0x60 => "Chorus Send Level",
+                0x70 => "Rx. Note Off",
+                0x80 => "Rx. Note On",
+                _ => return None,
+            };
+            (format!("Drum Map {map} Note {note} {param}"), None)
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "{name} = {}{}",
+        hex(data),
+        value_name.unwrap_or_default()
+    ))
+}
+
+fn roland(device: u8, msg: &[u8]) -> Option<String> {
+    let (model, rest) = msg.split_first()?;
+    let (command, rest) = rest.split_first()?;
+    let model_name = match *model {
+        ROLAND_MODEL_MT32 => "MT-32",
+        ROLAND_MODEL_GS => "GS",
+        ROLAND_MODEL_SC55_DISPLAY => "SC-55 Display",
+        _ => return None,
+    };
+    let command_name = match *command {
+        ROLAND_COMMAND_RQ1 => "RQ1",
+        ROLAND_COMMAND_DT1 => "DT1",
+        _ => return None,
+    };
+    let (checksum, body) = rest.split_last()?;
+    if body.len() < 3 {
+        return None;
+    }
+    let (addr, data) = body.split_at(3);
+    let addr = [addr[0], addr[1], addr[2]];
+
+    let known = if (*model == ROLAND_MODEL_GS) && (*command == ROLAND_COMMAND_DT1) {
+        gs_parameter(addr, data)
+    } else {
+        None
+    };
+    let mut ret = match known {
+        Some(known) => format!("{model_name} {known}"),
+        None => format!(
+            "Roland {model_name} {command_name} {} = {}",
+            hex(&addr),
+            hex(data)
+        ),
+    };
+    if device != 0x10 {
+        ret += &format!(" (device ID {device:02X})");
+    }
+    let expected = roland_checksum(body);
+    if *checksum != expected {
+        ret += &format!(" [checksum mismatch: {checksum:02X}, expected {expected:02X}]");
+    }
+    Some(ret)
+}
+
+fn xg_parameter(addr: [u8; 3], data: &[u8]) -> Option<String> {
+    let name = match addr {
+        [0x00, 0x00, 0x7E] if data == [0x00] => return Some("XG System On".into()),
+        [0x00, 0x00, 0x7D] => return Some("XG Drum Setup Reset".into()),
+        [0x00, 0x00, 0x7F] if data == [0x00] => return Some("XG All Parameter Reset".into()),
+        [0x00, 0x00, 0x04] => "Master Volume".into(),
+        [0x00, 0x00, 0x06] => "Transpose".into(),
+        [0x02, 0x01, 0x00] => "Reverb Type".into(),
+        [0x02, 0x01, 0x0C] => "Reverb Return".into(),
+        [0x02, 0x01, 0x20] => "Chorus Type".into(),
+        [0x02, 0x01, 0x2C] => "Chorus Return".into(),
+        [0x02, 0x01, 0x40] => "Variation Type".into(),
+        [0x08, part, param] => {
+            let param = match param {
+                0x01 => "Bank Select MSB",
+                0x02 => "Bank Select LSB",
+                0x03 => "Program Number",
+                0x04 => "Rcv Channel",
+                0x05 => "Mono/Poly Mode",
+                0x07 => "Part Mode",
+                0x08 => "Note Shift",
+                0x0B => "Volume",
+                0x0E => "Pan",
+                0x12 => "Chorus Send",
+                0x13 => "Reverb Send",
+                0x14 => "Variation Send",
+                _ => return None,
+            };
+            format!("Part {} {param}", part + 1)
+        }
+        _ => return None,
+    };
+    Some(format!("XG {name} = {}", hex(data)))
+}
+
+fn yamaha(device: u8, msg: &[u8]) -> Option<String> {
+    // Parameter change messages use 1n as the device number byte.
+    if (device & 0xF0) != 0x10 {
+        return None;
+    }
+    let (model, rest) = msg.split_first()?;
+    if (*model != YAMAHA_MODEL_XG) || (rest.len() < 4) {
+        return None;
+    }
+    let (addr, data) = rest.split_at(3);
+    let addr = [addr[0], addr[1], addr[2]];
+    xg_parameter(addr, data).or_else(|| {
+        Some(format!(
+            "XG Parameter Change {} = {}",
+            hex(&addr),
+            hex(data)
+        ))
+    })
+}
+
+/// Describes the given *SysEx* event data (excluding the initial `F0` byte), if it's a complete
+/// message in one of the supported formats. Roland messages with invalid checksums are described
+/// with a warning.
+pub fn describe(data: &[u8]) -> Option<String> {
+    let msg = data.strip_suffix(&[0xF7])?;
+    let (manufacturer, rest) = msg.split_first()?;
+    let (device, msg) = rest.split_first()?;
+    match *manufacturer {
+        UNIVERSAL_NON_REALTIME => universal_non_realtime(msg),
+        UNIVERSAL_REALTIME => universal_realtime(msg),
+        ROLAND => roland(*device, msg),
+        YAMAHA => yamaha(*device, msg),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_on() {
+        let describe = |data: &[u8]| describe(data).unwrap();
+        assert_eq!(describe(&[0x7E, 0x7F, 0x09, 0x01, 0xF7]), "GM System On");
+        assert_eq!(
+            describe(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7]),
+            "GS Reset"
+        );
+        assert_eq!(
+            describe(&[0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7]),
+            "XG System On"
+        );
+    }
+
+    #[test]
+    fn roland_parameter_change() {
+        assert_eq!(roland_checksum(&[0x40, 0x11, 0x19, 0x64]), 0x32);
+        assert_eq!(
+            describe(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x11, 0x19, 0x64, 0x32, 0xF7]).unwrap(),
+            "GS Part 1 Part Level = 64"
+        );
+
+        // Unknown addresses are shown verbatim.
+        assert_eq!(
+            describe(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x11, 0x7F, 0x05, 0x2B, 0xF7]).unwrap(),
+            "Roland GS DT1 40 11 7F = 05"
+        );
+    }
+
+    #[test]
+    fn roland_checksum_mismatch() {
+        assert_eq!(
+            describe(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x11, 0x19, 0x64, 0x33, 0xF7]).unwrap(),
+            "GS Part 1 Part Level = 64 [checksum mismatch: 33, expected 32]"
+        );
+    }
+}
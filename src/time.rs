@@ -1,69 +1,487 @@
 //! Timekeeping in various units.
 
-use std::{cmp::max, error::Error, ops::Range, str::FromStr, time::Duration};
+use std::{cmp::max, cmp::Ordering, error::Error, ops::Range, str::FromStr};
 
-use midly::{num::u15, num::u28, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use serde::Serialize;
 
+use crate::{
+    event,
+    output::{self, Format},
+};
+
+use midly::{num::u15, num::u28, Fps, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+/// Frame rate of an SMPTE timecode as a fraction of frames per second.
+fn fps_ratio(fps: Fps) -> (u64, u64) {
+    match fps {
+        Fps::Fps29 => (30000, 1001),
+        fps => (fps.as_int() as u64, 1),
+    }
+}
+
+/// Number of frames per second used for *labeling* SMPTE timecodes. 29.97 FPS timecodes are
+/// labeled as 30 FPS, with drop-frame numbering.
+fn fps_nominal(fps: Fps) -> u64 {
+    match fps {
+        Fps::Fps29 => 30,
+        fps => fps.as_int() as u64,
+    }
+}
+
+/// Frames per 10 minutes and per (non-10th) minute in 29.97 FPS drop-frame timecodes.
+const DF_FRAMES_PER_10_MINUTES: u64 = 17982;
+const DF_FRAMES_PER_MINUTE: u64 = 1798;
+
+/// Converts a frame count to a drop-frame-adjusted count of nominal 30 FPS frames, which can then
+/// be split into timecode components.
+fn df_frame_label(frame: u64) -> u64 {
+    let tens = frame / DF_FRAMES_PER_10_MINUTES;
+    let rem = frame % DF_FRAMES_PER_10_MINUTES;
+    let dropped_minutes = if rem > 1 {
+        (rem - 2) / DF_FRAMES_PER_MINUTE
+    } else {
+        0
+    };
+    frame + (18 * tens) + (2 * dropped_minutes)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Realtime duration of a number of SMPTE ticks (subframes).
+fn timecode_realtime(fps: Fps, subframes: u8, pulse: u64) -> Realtime {
+    let (frames, secs) = fps_ratio(fps);
+    Realtime {
+        num: (pulse as u128) * 1_000_000 * (secs as u128),
+        den: frames * (subframes as u64),
+    }
+}
+
+/// Exact realtime value, stored as a fraction of microseconds to avoid any accumulation of rounding
+/// errors. For sequences with metrical timing, the denominator is the PPQN value, which turns the
+/// numerator into the sum of all *microseconds per quarter note × pulses* products.
 #[derive(Clone, Copy, Debug)]
-pub struct MidiTime {
-    pulse: u64,
-    realtime: Option<Duration>,
-    qn_duration: Option<Duration>, // a.k.a. "tempo"
+pub struct Realtime {
+    num: u128,
+    den: u64,
+}
 
-    ppqn: u16,
-    samplerate: Option<u32>,
+impl Realtime {
+    fn zero(timing: &Timing) -> Self {
+        let den = match timing {
+            Timing::Metrical(ppqn) => ppqn.as_int() as u64,
+            Timing::Timecode(fps, subframes) => fps_ratio(*fps).0 * (*subframes as u64),
+        };
+        Realtime { num: 0, den }
+    }
+
+    fn from_nanos(nanos: u128) -> Self {
+        Realtime {
+            num: nanos,
+            den: 1000,
+        }
+    }
+
+    /// Divides the realtime by the given unit in microseconds, and rounds to the nearest integer.
+    /// Halfway cases are rounded up.
+    fn round_to(&self, unit_num: u128, unit_den: u128) -> u128 {
+        let num = self.num * unit_den;
+        let den = (self.den as u128) * unit_num;
+        ((2 * num) + den) / (2 * den)
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        (self.num / ((self.den as u128) * 1_000_000)) as u64
+    }
+
+    /// Returns the number of milliseconds, rounded to the nearest integer.
+    pub fn millis(&self) -> u128 {
+        self.round_to(1000, 1)
+    }
+
+    /// Returns the position in PCM samples at the given sampling rate, rounded to the nearest
+    /// sample. Halfway cases are rounded up.
+    pub fn sample(&self, samplerate: u32) -> u64 {
+        self.round_to(1_000_000, samplerate as u128) as u64
+    }
+
+    pub fn as_micros_f64(&self) -> f64 {
+        (self.num as f64) / (self.den as f64)
+    }
 }
 
-impl MidiTime {
-    pub fn new(timing: &Timing, samplerate: Option<u32>) -> Self {
-        MidiTime {
-            pulse: 0,
-            realtime: None,
-            qn_duration: None,
-            ppqn: match timing {
-                Timing::Metrical(ppqn) => ppqn.as_int(),
-                Timing::Timecode(_, _) => unimplemented!("ticks/second not supported"),
-            },
-            samplerate,
+/// Returns the duration between two realtime values, which requires `other` to not be later than
+/// `self`. The result is expressed using the least common denominator of both values.
+impl std::ops::Sub for Realtime {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        debug_assert!(other <= self, "subtracting {other:?} from earlier {self:?}");
+        let den = (self.den / gcd(self.den, other.den)) * other.den;
+        let num_self = self.num * ((den / self.den) as u128);
+        let num_other = other.num * ((den / other.den) as u128);
+        Realtime {
+            num: num_self.saturating_sub(num_other),
+            den,
         }
     }
+}
+
+/// Formats the realtime value in *minutes:seconds:milliseconds* format.
+impl std::fmt::Display for Realtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Truncating the milliseconds is not all too nice. Note that we have to preserve the carry
+        // in case we round up from 999 to 1000 milliseconds – I was very fortunate to have this
+        // case happen in my tests!
+        let total_millis = self.millis();
+        let millis = (total_millis % 1000) as u16;
+        let seconds = ((total_millis / 1000) % 60) as u8;
+        let minutes = (total_millis / 1000) / 60;
+        f.pad(&format!("{minutes}:{seconds:02}:{millis:03}m"))
+    }
+}
+
+impl PartialEq for Realtime {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Realtime {}
+
+impl PartialOrd for Realtime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Realtime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num * (other.den as u128)).cmp(&(other.num * (self.den as u128)))
+    }
+}
+
+/// SMPTE timecode position in *hours:minutes:seconds:frames.subframes* format.
+#[derive(Clone, Copy, Debug)]
+struct Timecode {
+    hours: u64,
+    minutes: u8,
+    seconds: u8,
+    frames: u8,
+    subframes: u8,
+}
+
+impl Timecode {
+    fn from_pulse(pulse: u64, fps: Fps, subframes_per_frame: u8) -> Self {
+        let subframes = (pulse % (subframes_per_frame as u64)) as u8;
+        let frame = pulse / (subframes_per_frame as u64);
+        let frame = match fps {
+            Fps::Fps29 => df_frame_label(frame),
+            _ => frame,
+        };
+        let fps = fps_nominal(fps);
+        let seconds_total = frame / fps;
+        Timecode {
+            hours: seconds_total / 3600,
+            minutes: ((seconds_total / 60) % 60) as u8,
+            seconds: (seconds_total % 60) as u8,
+            frames: (frame % fps) as u8,
+            subframes,
+        }
+    }
+
+    fn total_pulse(&self, fps: Fps, subframes_per_frame: u8) -> Result<u64, String> {
+        let fps_label = fps_nominal(fps);
+        if (self.frames as u64) >= fps_label {
+            return Err(format!(
+                "frame {} out of range for {} FPS timecode",
+                self.frames, fps_label
+            ));
+        }
+        if self.subframes >= subframes_per_frame {
+            return Err(format!(
+                "subframe {} out of range for {subframes_per_frame} subframes per frame",
+                self.subframes
+            ));
+        }
+        let minutes_total = (self.hours * 60) + (self.minutes as u64);
+        let seconds_total = (minutes_total * 60) + (self.seconds as u64);
+        let mut frame = (seconds_total * fps_label) + (self.frames as u64);
+        if fps == Fps::Fps29 {
+            if (self.seconds == 0) && (self.frames < 2) && !self.minutes.is_multiple_of(10) {
+                return Err(format!(
+                    "frame {} does not exist in drop-frame timecode",
+                    self.frames
+                ));
+            }
+            frame -= 2 * (minutes_total - (minutes_total / 10));
+        }
+        Ok((frame * (subframes_per_frame as u64)) + (self.subframes as u64))
+    }
+}
+
+/// Tempo of sequences without a *Tempo* event at pulse 0, as defined by the SMF specification.
+const QN_MICROS_DEFAULT: u64 = 500_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MidiTime {
+    pulse: u64,
+    realtime: Realtime,
+    qn_micros: u64, // a.k.a. "tempo"
 
+    timing: Timing,
+}
+
+impl MidiTime {
     pub fn pulse(&self) -> u64 {
         self.pulse
     }
 
-    pub fn sample(&self) -> Option<f64> {
-        self.samplerate
-            .zip(self.realtime)
-            .map(|(r, t)| t.as_secs_f64() * r as f64)
+    pub fn realtime(&self) -> Realtime {
+        self.realtime
     }
-}
 
-impl std::ops::Add<&TrackEvent<'_>> for MidiTime {
-    type Output = Self;
+    pub fn sample(&self, samplerate: u32) -> u64 {
+        self.realtime.sample(samplerate)
+    }
 
-    fn add(self, ev: &TrackEvent<'_>) -> Self {
-        let pulse = self.pulse + ev.delta.as_int() as u64;
-        let realtime = self
-            .qn_duration
-            .filter(|_| self.realtime.is_some() || pulse == 0)
-            .map(|q| {
-                self.realtime.unwrap_or_default()
-                    + q.mul_f64(ev.delta.as_int().into())
-                        .div_f64(self.ppqn as f64)
-            });
-        let qn_duration = if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = ev.kind {
-            Some(Duration::from_micros(tempo.as_int().into()))
-        } else {
-            self.qn_duration
+    /// Returns the tempo in microseconds per quarter note.
+    pub fn qn_micros(&self) -> u64 {
+        self.qn_micros
+    }
+
+    pub fn bpm(&self) -> f64 {
+        60_000_000.0 / (self.qn_micros as f64)
+    }
+
+    /// Returns the time `pulses` after this one, assuming no tempo change in between.
+    fn advance(&self, pulses: u64) -> Self {
+        let pulse = self.pulse + pulses;
+        let realtime = match self.timing {
+            Timing::Metrical(_) => Realtime {
+                num: self.realtime.num + (self.qn_micros as u128) * (pulses as u128),
+                ..self.realtime
+            },
+            // Ticks are subframes, which directly map to realtime regardless of any tempo events.
+            Timing::Timecode(fps, subframes) => timecode_realtime(fps, subframes, pulse),
         };
         MidiTime {
             pulse,
             realtime,
-            qn_duration,
-            ppqn: self.ppqn,
-            samplerate: self.samplerate,
+            ..*self
+        }
+    }
+}
+
+/// Position in 0-based *bar:beat:tick* format. Displayed with 1-based bars and beats, as common
+/// in sequencers.
+#[derive(Clone, Copy, Debug)]
+pub struct BarBeatTick {
+    pub bar: u64,
+    pub beat: u64,
+    pub tick: u64,
+}
+
+/// Time signature section of a sequence.
+#[derive(Clone, Copy, Debug)]
+struct Meter {
+    pulse: u64,
+    bar: u64, // 0-based index of the first bar in this section
+    numerator: u8,
+    denominator_pow2: u8,
+    beat_len: u64, // in pulses
+}
+
+impl Meter {
+    fn new(pulse: u64, bar: u64, numerator: u8, denominator_pow2: u8, ppqn: u64) -> Self {
+        let beat_len = (ppqn * 4).checked_shr(denominator_pow2.into()).unwrap_or(0);
+        Meter {
+            pulse,
+            bar,
+            numerator: max(numerator, 1),
+            denominator_pow2,
+            beat_len: max(beat_len, 1),
+        }
+    }
+
+    fn bar_len(&self) -> u64 {
+        (self.numerator as u64) * self.beat_len
+    }
+}
+
+/// Global tempo map of a sequence.
+///
+/// As required for SMF Type 1 sequences, this map is built from the *Tempo* and *Time Signature*
+/// meta events of all tracks. Simultaneous events are applied in track order.
+#[derive(Debug)]
+pub struct TempoMap {
+    /// Time at every tempo change, sorted by pulse. Always starts at pulse 0.
+    changes: Vec<MidiTime>,
+
+    /// Sorted by pulse. Always starts at pulse 0 for sequences with metrical timing, and is empty
+    /// for sequences with SMPTE timing.
+    meters: Vec<Meter>,
+}
+
+impl TempoMap {
+    pub fn new(smf: &Smf) -> Self {
+        let mut tempos = Vec::new();
+        let mut time_signatures = Vec::new();
+        for track in &smf.tracks {
+            let mut pulse = 0;
+            for ev in track {
+                pulse += ev.delta.as_int() as u64;
+                match ev.kind {
+                    TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                        tempos.push((pulse, tempo.as_int() as u64));
+                    }
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(num, den, _, _)) => {
+                        time_signatures.push((pulse, num, den));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // The sorts are stable, which preserves the track order of simultaneous events.
+        tempos.sort_by_key(|(pulse, _)| *pulse);
+        time_signatures.sort_by_key(|(pulse, _, _)| *pulse);
+        let mut changes = vec![MidiTime {
+            pulse: 0,
+            realtime: Realtime::zero(&smf.header.timing),
+            qn_micros: QN_MICROS_DEFAULT,
+            timing: smf.header.timing,
+        }];
+        for (pulse, qn_micros) in tempos {
+            let last = changes.last_mut().unwrap();
+            if last.pulse == pulse {
+                last.qn_micros = qn_micros;
+            } else {
+                let time = last.advance(pulse - last.pulse);
+                changes.push(MidiTime { qn_micros, ..time });
+            }
         }
+
+        let mut meters = vec![];
+        if let Timing::Metrical(ppqn) = smf.header.timing {
+            let ppqn = ppqn.as_int() as u64;
+            meters.push(Meter::new(0, 0, 4, 2, ppqn));
+            for (pulse, numerator, denominator_pow2) in time_signatures {
+                let last = meters.last_mut().unwrap();
+                let bar = if last.pulse == pulse {
+                    last.bar
+                } else {
+                    // A time signature change in the middle of a bar ends that bar prematurely.
+                    last.bar + (pulse - last.pulse).div_ceil(last.bar_len())
+                };
+                let meter = Meter::new(pulse, bar, numerator, denominator_pow2, ppqn);
+                if last.pulse == pulse {
+                    *last = meter;
+                } else {
+                    meters.push(meter);
+                }
+            }
+        }
+        TempoMap { changes, meters }
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.changes[0].timing
+    }
+
+    pub fn time_at(&self, pulse: u64) -> MidiTime {
+        let i = self.changes.partition_point(|c| c.pulse <= pulse);
+        let change = &self.changes[i - 1];
+        change.advance(pulse - change.pulse)
+    }
+
+    /// Returns the *bar:beat:tick* position of the given pulse, or `None` for sequences with SMPTE
+    /// timing.
+    pub fn bar_at(&self, pulse: u64) -> Option<BarBeatTick> {
+        let i = self.meters.partition_point(|m| m.pulse <= pulse);
+        let meter = self.meters.get(i.checked_sub(1)?)?;
+        let elapsed = pulse - meter.pulse;
+        let in_bar = elapsed % meter.bar_len();
+        Some(BarBeatTick {
+            bar: meter.bar + (elapsed / meter.bar_len()),
+            beat: in_bar / meter.beat_len,
+            tick: in_bar % meter.beat_len,
+        })
+    }
+
+    /// Returns the numerator and denominator of the time signature at the given pulse, or `None`
+    /// for sequences with SMPTE timing.
+    pub fn time_signature_at(&self, pulse: u64) -> Option<(u8, u64)> {
+        let i = self.meters.partition_point(|m| m.pulse <= pulse);
+        let meter = self.meters.get(i.checked_sub(1)?)?;
+        let denominator = 1_u64
+            .checked_shl(meter.denominator_pow2.into())
+            .unwrap_or(0);
+        Some((meter.numerator, denominator))
+    }
+
+    /// Returns the pulse at the given 0-based *bar:beat:tick* position.
+    fn pulse_at_bar(&self, pos: BarBeatTick) -> Result<u64, String> {
+        let i = self.meters.partition_point(|m| m.bar <= pos.bar);
+        let Some(meter) = i.checked_sub(1).and_then(|i| self.meters.get(i)) else {
+            return Err("bars are not supported for sequences with SMPTE timing".into());
+        };
+        if pos.beat >= (meter.numerator as u64) {
+            return Err(format!(
+                "beat {} out of range for a bar with {} beats",
+                pos.beat + 1,
+                meter.numerator
+            ));
+        }
+        if pos.tick >= meter.beat_len {
+            return Err(format!(
+                "tick {} out of range for a beat with {} ticks",
+                pos.tick, meter.beat_len
+            ));
+        }
+        Ok(meter.pulse
+            + ((pos.bar - meter.bar) * meter.bar_len())
+            + (pos.beat * meter.beat_len)
+            + pos.tick)
+    }
+
+    /// Returns the pulse closest to the given realtime.
+    pub fn pulse_at_realtime(&self, realtime: Realtime) -> u64 {
+        let i = self.changes.partition_point(|c| c.realtime <= realtime);
+        let change = &self.changes[i.saturating_sub(1)];
+        // Realtime values before pulse 0 can only occur with an empty sequence.
+        let offset_num = (realtime.num * (change.realtime.den as u128))
+            .saturating_sub(change.realtime.num * (realtime.den as u128));
+        let offset_den = (realtime.den as u128) * (change.realtime.den as u128);
+        let (num, den) = match change.timing {
+            Timing::Metrical(ppqn) => (
+                offset_num * (ppqn.as_int() as u128),
+                offset_den * (change.qn_micros as u128),
+            ),
+            Timing::Timecode(fps, subframes) => {
+                let (frames, secs) = fps_ratio(fps);
+                (
+                    offset_num * (frames as u128) * (subframes as u128),
+                    offset_den * (secs as u128) * 1_000_000,
+                )
+            }
+        };
+        change.pulse + (((2 * num) + den) / (2 * den)) as u64
+    }
+
+    /// Returns the time at the end of the track with the highest final MIDI pulse value.
+    pub fn time_at_end(&self, smf: &Smf) -> MidiTime {
+        let pulse = smf
+            .tracks
+            .iter()
+            .map(|track| track.iter().map(|ev| ev.delta.as_int() as u64).sum())
+            .max()
+            .unwrap_or(0);
+        self.time_at(pulse)
     }
 }
 
@@ -74,69 +492,182 @@ pub struct UnitWidths {
     pub beat: usize,
     pub beat_qn: usize,
     pub beat_pulse: usize,
-    pub minutes: usize,
+    pub timecode_hours: usize,
+    pub timecode_subframes: usize,
+    pub bar: usize,
+    pub bar_number: usize,
+    pub bar_beat: usize,
+    pub bar_tick: usize,
+    pub realtime: usize,
     pub sample: usize,
 }
 
 /// Provides formatting for `MidiTime`.
 #[derive(Debug)]
-pub struct MidiTimeDisplay {
+pub struct MidiTimeDisplay<'a> {
+    tempo_map: &'a TempoMap,
     pub time: MidiTime,
+    samplerate: Option<u32>,
     widths: UnitWidths,
 }
 
-pub struct MidiTimeDisplayPulse<'a>(&'a MidiTimeDisplay);
-pub struct MidiTimeDisplayBeat<'a>(&'a MidiTimeDisplay);
+pub struct MidiTimeDisplayPulse<'a>(&'a MidiTimeDisplay<'a>);
+pub struct MidiTimeDisplayBeat<'a>(&'a MidiTimeDisplay<'a>);
+pub struct MidiTimeDisplayBar<'a>(&'a MidiTimeDisplay<'a>, BarBeatTick);
+pub struct MidiTimeDisplayRealtime<'a>(&'a MidiTimeDisplay<'a>);
+pub struct MidiTimeDisplaySample<'a>(&'a MidiTimeDisplay<'a>, u64);
 
-impl MidiTimeDisplay {
-    fn with_limits(start: MidiTime, end: &MidiTime, delta_max: u28) -> Self {
-        let beat_qn_width = max(end.pulse / (end.ppqn as u64), 1).ilog10() + 1;
-        let beat_pulse_width = max(end.ppqn, 1).ilog10() + 1;
-        let minutes_width = max(end.realtime.map_or(1, |d| d.as_secs() / 60), 1).ilog10() + 1;
+/// Machine-readable representation of a `MidiTime` in all available units. All positions use the
+/// same formats as `B/P` parameters.
+#[derive(Serialize)]
+pub struct TimeRecord {
+    pub pulse: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beat: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timecode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bar: Option<String>,
+    pub realtime_us: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<u64>,
+}
+
+impl<'a> MidiTimeDisplay<'a> {
+    fn with_limits(
+        tempo_map: &'a TempoMap,
+        start: MidiTime,
+        end: &MidiTime,
+        delta_max: u28,
+        samplerate: Option<u32>,
+    ) -> Self {
+        let (mut beat_qn_width, mut beat_pulse_width) = (0, 0);
+        let (mut timecode_hours_width, mut timecode_subframes_width) = (0, 0);
+        let beat_width = match end.timing {
+            Timing::Metrical(ppqn) => {
+                let ppqn = ppqn.as_int() as u64;
+                beat_qn_width = max(end.pulse / ppqn, 1).ilog10() + 1;
+                beat_pulse_width = max(ppqn, 1).ilog10() + 1;
+                beat_qn_width + 1 + beat_pulse_width
+            }
+            Timing::Timecode(fps, subframes) => {
+                let hours = Timecode::from_pulse(end.pulse, fps, subframes).hours;
+                timecode_hours_width = max(hours, 1).ilog10() + 1;
+                timecode_subframes_width = max(subframes.saturating_sub(1), 1).ilog10() + 1;
+                timecode_hours_width + ":mm:ss:ff.".len() as u32 + timecode_subframes_width
+            }
+        };
+        let meters = &tempo_map.meters;
+        let bar_number_width = tempo_map
+            .bar_at(end.pulse)
+            .map_or(0, |b| (b.bar + 1).ilog10() + 1);
+        let bar_beat_width = meters.iter().map(|m| m.numerator.ilog10() + 1).max();
+        let bar_tick_width = meters
+            .iter()
+            .map(|m| max(m.beat_len - 1, 1).ilog10() + 1)
+            .max();
+        let bar_width = bar_beat_width
+            .zip(bar_tick_width)
+            .map_or(0, |(b, t)| bar_number_width + 1 + b + 1 + t);
+        let minutes_width = max(end.realtime.as_secs() / 60, 1).ilog10() + 1;
+        let realtime_width = minutes_width + ":ss:mmmm".len() as u32;
+        let sample_end = samplerate.map_or(1, |r| end.sample(r));
 
         MidiTimeDisplay {
+            tempo_map,
             time: start,
+            samplerate,
             widths: UnitWidths {
                 delta: ((max(delta_max.as_int(), 1).ilog10() + 1) + 1) as usize,
                 pulse: (max(end.pulse, 1).ilog10() + 1) as usize,
-                beat: (beat_qn_width + 1 + beat_pulse_width) as usize,
+                beat: beat_width as usize,
                 beat_qn: beat_qn_width as usize,
                 beat_pulse: beat_pulse_width as usize,
-                minutes: minutes_width as usize,
-                sample: (max(end.sample().unwrap_or(1.0) as u64, 1).ilog10() + 1) as usize + 3,
+                timecode_hours: timecode_hours_width as usize,
+                timecode_subframes: timecode_subframes_width as usize,
+                bar: bar_width as usize,
+                bar_number: bar_number_width as usize,
+                bar_beat: bar_beat_width.unwrap_or(0) as usize,
+                bar_tick: bar_tick_width.unwrap_or(0) as usize,
+                realtime: realtime_width as usize,
+                sample: (max(sample_end, 1).ilog10() + 1) as usize,
             },
         }
     }
 
-    pub fn new(timing: &Timing, track: &[TrackEvent], samplerate: Option<u32>) -> Self {
-        let time_init = MidiTime::new(timing, samplerate);
-        let (delta_max, end) = track.iter().fold((0.into(), time_init), |acc, ev| {
-            (max(acc.0, ev.delta), (acc.1 + ev))
+    pub fn new(tempo_map: &'a TempoMap, track: &[TrackEvent], samplerate: Option<u32>) -> Self {
+        let (delta_max, pulse_end) = track.iter().fold((0.into(), 0), |acc, ev| {
+            (max(acc.0, ev.delta), (acc.1 + ev.delta.as_int() as u64))
         });
-        Self::with_limits(time_init, &end, delta_max)
+        let time_init = tempo_map.time_at(0);
+        let end = tempo_map.time_at(pulse_end);
+        Self::with_limits(tempo_map, time_init, &end, delta_max, samplerate)
     }
 
-    pub fn new_at_end(smf: &Smf, samplerate: Option<u32>) -> Self {
-        let time_init = MidiTime::new(&smf.header.timing, samplerate);
-        let end = smf
-            .tracks
-            .iter()
-            .map(|track| track.iter().fold(time_init, |acc, ev| (acc + ev)))
-            .max_by(|a, b| a.pulse.cmp(&b.pulse))
-            .unwrap_or(time_init);
-        Self::with_limits(end, &end, 0.into())
+    pub fn new_at_end(tempo_map: &'a TempoMap, smf: &Smf, samplerate: Option<u32>) -> Self {
+        let end = tempo_map.time_at_end(smf);
+        Self::with_limits(tempo_map, end, &end, 0.into(), samplerate)
+    }
+
+    pub fn new_at(tempo_map: &'a TempoMap, pulse: u64, samplerate: Option<u32>) -> Self {
+        let time = tempo_map.time_at(pulse);
+        Self::with_limits(tempo_map, time, &time, 0.into(), samplerate)
+    }
+
+    /// Advances the displayed time by the delta time of the given event.
+    pub fn advance(&mut self, ev: &TrackEvent) {
+        self.time = self
+            .tempo_map
+            .time_at(self.time.pulse + ev.delta.as_int() as u64);
     }
 
     pub fn widths(&self) -> UnitWidths {
         self.widths.clone()
     }
 
-    pub fn display_pulse(&self) -> MidiTimeDisplayPulse {
+    /// Name of the unit shown by `display_beat()`. Sequences with SMPTE timing have no concept of
+    /// beats, and show the SMPTE timecode in its place.
+    pub fn beat_unit(&self) -> &'static str {
+        match self.time.timing {
+            Timing::Metrical(_) => "beat",
+            Timing::Timecode(_, _) => "timecode",
+        }
+    }
+
+    pub fn display_pulse(&self) -> MidiTimeDisplayPulse<'_> {
         MidiTimeDisplayPulse(self)
     }
-    pub fn display_beat(&self) -> MidiTimeDisplayBeat {
+    pub fn display_beat(&self) -> MidiTimeDisplayBeat<'_> {
         MidiTimeDisplayBeat(self)
     }
+    pub fn display_bar(&self) -> Option<MidiTimeDisplayBar<'_>> {
+        let pos = self.tempo_map.bar_at(self.time.pulse)?;
+        Some(MidiTimeDisplayBar(self, pos))
+    }
+    pub fn display_realtime(&self) -> MidiTimeDisplayRealtime<'_> {
+        MidiTimeDisplayRealtime(self)
+    }
+    pub fn display_sample(&self) -> Option<MidiTimeDisplaySample<'_>> {
+        let sample = self.time.sample(self.samplerate?);
+        Some(MidiTimeDisplaySample(self, sample))
+    }
+
+    pub fn record(&self) -> TimeRecord {
+        let unpadded = Self::new_at(self.tempo_map, self.time.pulse, self.samplerate);
+        let beat = unpadded.display_beat().to_string();
+        let (beat, timecode) = match self.time.timing {
+            Timing::Metrical(_) => (Some(beat), None),
+            Timing::Timecode(_, _) => (None, Some(beat)),
+        };
+        TimeRecord {
+            pulse: self.time.pulse,
+            beat,
+            timecode,
+            bar: unpadded.display_bar().map(|bar| format!("b{bar}")),
+            realtime_us: self.time.realtime.as_micros_f64(),
+            sample: unpadded.display_sample().map(|s| s.1),
+        }
+    }
 }
 
 impl<'a> std::fmt::Display for MidiTimeDisplayPulse<'a> {
@@ -149,33 +680,70 @@ impl<'a> std::fmt::Display for MidiTimeDisplayPulse<'a> {
 
 impl<'a> std::fmt::Display for MidiTimeDisplayBeat<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let qn = self.0.time.pulse / (self.0.time.ppqn as u64);
-        let pulse = self.0.time.pulse % (self.0.time.ppqn as u64);
-        let qn_width = self.0.widths.beat_qn;
-        let pulse_width = self.0.widths.beat_pulse;
-        write!(f, "{qn:>qn_width$}:{pulse:>0pulse_width$}")
+        match self.0.time.timing {
+            Timing::Metrical(ppqn) => {
+                let qn = self.0.time.pulse / (ppqn.as_int() as u64);
+                let pulse = self.0.time.pulse % (ppqn.as_int() as u64);
+                let qn_width = self.0.widths.beat_qn;
+                let pulse_width = self.0.widths.beat_pulse;
+                write!(f, "{qn:>qn_width$}:{pulse:>0pulse_width$}")
+            }
+            Timing::Timecode(fps, subframes) => {
+                let tc = Timecode::from_pulse(self.0.time.pulse, fps, subframes);
+                let hours_width = self.0.widths.timecode_hours;
+                let subframes_width = self.0.widths.timecode_subframes;
+                write!(
+                    f,
+                    "{:>hours_width$}:{:02}:{:02}:{:02}.{:0subframes_width$}",
+                    tc.hours, tc.minutes, tc.seconds, tc.frames, tc.subframes
+                )
+            }
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for MidiTimeDisplayBar<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let BarBeatTick { bar, beat, tick } = self.1;
+        let bar_width = self.0.widths.bar_number;
+        let beat_width = self.0.widths.bar_beat;
+        let tick_width = self.0.widths.bar_tick;
+        let (bar, beat) = (bar + 1, beat + 1);
+        write!(
+            f,
+            "{bar:>bar_width$}.{beat:>beat_width$}.{tick:0tick_width$}"
+        )
+    }
+}
+
+impl<'a> std::fmt::Display for MidiTimeDisplayRealtime<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let realtime = self.0.time.realtime;
+        let realtime_width = self.0.widths.realtime;
+        write!(f, "{realtime:>realtime_width$}")
+    }
+}
+
+impl<'a> std::fmt::Display for MidiTimeDisplaySample<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sample = self.1;
+        let sample_width = self.0.widths.sample;
+        write!(f, "{sample:>sample_width$}")
     }
 }
 
-impl std::fmt::Display for MidiTimeDisplay {
+impl std::fmt::Display for MidiTimeDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let pulse = self.display_pulse();
         let beat = self.display_beat();
-        write!(f, "pulse {pulse} / beat {beat}")?;
-        if let Some(realtime) = self.time.realtime {
-            // Duration::subsec_millis() truncates, which is not all too nice. Note that we have to
-            // preserve the carry in case we round up from 999 to 1000 milliseconds – I was very
-            // fortunate to have this case happen in my tests!
-            let total_millis = (realtime.as_micros() as f64 / 1000.0).round() as u128;
-            let millis = (total_millis % 1000) as u16;
-            let seconds = ((total_millis / 1000) % 60) as u8;
-            let minutes = ((total_millis / 1000) / 60) % 60;
-            let minutes_width = self.widths.minutes;
-            write!(f, " / {minutes:>minutes_width$}:{seconds:02}:{millis:03}m")?;
-        }
-        if let Some(sample) = self.time.sample() {
-            let sample_width = self.widths.sample;
-            write!(f, " / sample {sample:>sample_width$.2}")?;
+        let beat_unit = self.beat_unit();
+        write!(f, "pulse {pulse} / {beat_unit} {beat}")?;
+        if let Some(bar) = self.display_bar() {
+            write!(f, " / bar {bar}")?;
+        }
+        write!(f, " / {}", self.display_realtime())?;
+        if let Some(sample) = self.display_sample() {
+            write!(f, " / sample {sample}")?;
         }
         Ok(())
     }
@@ -185,7 +753,7 @@ impl std::fmt::Display for MidiTimeDisplay {
 #[derive(Debug)]
 pub struct PulseOutOfRange {
     pulse: u64,
-    len: MidiTimeDisplay,
+    len: String,
 }
 
 impl std::fmt::Display for PulseOutOfRange {
@@ -198,8 +766,10 @@ impl std::fmt::Display for PulseOutOfRange {
 impl std::error::Error for PulseOutOfRange {}
 
 pub fn validate_pulse(smf: &Smf, pulse: u64) -> Result<(), PulseOutOfRange> {
-    let len = MidiTimeDisplay::new_at_end(smf, None);
+    let tempo_map = TempoMap::new(smf);
+    let len = MidiTimeDisplay::new_at_end(&tempo_map, smf, None);
     if pulse > len.time.pulse() {
+        let len = len.to_string();
         return Err(PulseOutOfRange { pulse, len });
     }
     Ok(())
@@ -209,23 +779,53 @@ pub fn validate_pulse_range(
     smf: &Smf,
     range: (u64, Option<u64>),
 ) -> Result<Range<u64>, PulseOutOfRange> {
-    let len = MidiTimeDisplay::new_at_end(smf, None);
+    let tempo_map = TempoMap::new(smf);
+    let len = MidiTimeDisplay::new_at_end(&tempo_map, smf, None);
     let ret = range.0..range.1.unwrap_or(len.time.pulse());
     if ret.start > len.time.pulse() {
-        let pulse = ret.start;
+        let (pulse, len) = (ret.start, len.to_string());
         return Err(PulseOutOfRange { pulse, len });
     } else if ret.end > len.time.pulse() {
-        let pulse = ret.end;
+        let (pulse, len) = (ret.end, len.to_string());
         return Err(PulseOutOfRange { pulse, len });
     }
     Ok(ret)
 }
 
-/// Stores a MIDI pulse in either total pulse or quarter-note:pulse format.
+/// Stores a MIDI pulse in either total pulse, quarter-note:pulse, bar:beat:tick, or SMPTE timecode
+/// format, or a realtime or PCM sample position that gets rounded to the nearest pulse.
 #[derive(Clone, Debug)]
 enum PulseOrBeatValue {
     Pulse(u64),
     Beat(u64, u15),
+    Bar(BarBeatTick),
+    Timecode(Timecode),
+    Realtime(Realtime),
+    Sample(u64),
+}
+
+/// Parses a realtime value in *[[hours:]minutes:]seconds[.fraction]* format.
+fn parse_realtime(s: &str) -> Result<Realtime, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let (s_hm, s_seconds) = s.rsplit_once(':').unwrap_or(("", s));
+    let (s_seconds, s_fraction) = s_seconds.split_once('.').unwrap_or((s_seconds, ""));
+    let mut seconds: u64 = if s_seconds.is_empty() {
+        0
+    } else {
+        str::parse(s_seconds)?
+    };
+    for (i, s_unit) in s_hm.rsplit(':').enumerate().filter(|(_, s)| !s.is_empty()) {
+        if i >= 2 {
+            return Err("realtime values can't have more than 3 components".into());
+        }
+        seconds += str::parse::<u64>(s_unit)? * 60_u64.pow((i + 1) as u32);
+    }
+    if s_fraction.len() > 9 || !s_fraction.bytes().all(|c| c.is_ascii_digit()) {
+        return Err("realtime fractions must consist of at most 9 decimal digits".into());
+    }
+    let nanos = format!("{s_fraction:0<9}").parse::<u32>()?;
+    Ok(Realtime::from_nanos(
+        ((seconds as u128) * 1_000_000_000) + (nanos as u128),
+    ))
 }
 
 #[derive(Clone, Debug)]
@@ -235,14 +835,62 @@ pub struct PulseOrBeat {
 }
 
 impl PulseOrBeat {
-    pub fn total_pulse(&self, timing: &Timing) -> Result<u64, &'static str> {
-        match self.value {
-            PulseOrBeatValue::Pulse(pulse) => Ok(pulse),
-            PulseOrBeatValue::Beat(qn, pulse) => match timing {
-                Timing::Metrical(ppqn) => Ok(qn * (ppqn.as_int() as u64) + pulse.as_int() as u64),
-                Timing::Timecode(_, _) => Err("ticks/second not supported"),
-            },
+    /// Resolves the position to a total pulse, and reports any rounded realtime and sample
+    /// positions on stderr.
+    pub fn total_pulse(
+        &self,
+        tempo_map: &TempoMap,
+        samplerate: Option<u32>,
+    ) -> Result<u64, String> {
+        let pulse = self.resolve(tempo_map, samplerate)?;
+        if matches!(
+            self.value,
+            PulseOrBeatValue::Realtime(_) | PulseOrBeatValue::Sample(_)
+        ) {
+            let time = MidiTimeDisplay::new_at(tempo_map, pulse, samplerate);
+            eprintln!("`{}` → {time}", self.input);
         }
+        Ok(pulse)
+    }
+
+    /// Resolves the position to a total pulse, rounding realtime and sample positions to the
+    /// nearest pulse.
+    pub fn resolve(&self, tempo_map: &TempoMap, samplerate: Option<u32>) -> Result<u64, String> {
+        let realtime = match self.value {
+            PulseOrBeatValue::Pulse(pulse) => return Ok(pulse),
+            PulseOrBeatValue::Beat(qn, pulse) => {
+                return match tempo_map.timing() {
+                    Timing::Metrical(ppqn) => {
+                        Ok(qn * (ppqn.as_int() as u64) + pulse.as_int() as u64)
+                    }
+                    Timing::Timecode(_, _) => {
+                        Err("beats are not supported for sequences with SMPTE timing".into())
+                    }
+                }
+            }
+            PulseOrBeatValue::Bar(pos) => return tempo_map.pulse_at_bar(pos),
+            PulseOrBeatValue::Timecode(tc) => {
+                return match tempo_map.timing() {
+                    Timing::Metrical(_) => Err(
+                        "SMPTE timecodes are only supported for sequences with SMPTE timing".into(),
+                    ),
+                    Timing::Timecode(fps, subframes) => tc.total_pulse(fps, subframes),
+                }
+            }
+            PulseOrBeatValue::Realtime(realtime) => realtime,
+            PulseOrBeatValue::Sample(sample) => {
+                let Some(samplerate) = samplerate else {
+                    return Err(
+                        "sample positions require the global `-r`/`--samplerate` option".into(),
+                    );
+                };
+                Realtime {
+                    num: (sample as u128) * 1_000_000,
+                    den: samplerate as u64,
+                }
+            }
+        };
+        Ok(tempo_map.pulse_at_realtime(realtime))
     }
 }
 
@@ -250,7 +898,42 @@ impl FromStr for PulseOrBeat {
     type Err = Box<dyn std::error::Error + Send + Sync + 'static>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let value = if let Some((s_qn, s_pulse)) = s.split_once(':') {
+        let value = if let Some(s_realtime) = s.strip_suffix('s') {
+            PulseOrBeatValue::Realtime(parse_realtime(s_realtime)?)
+        } else if let Some(s_sample) = s.strip_prefix('s') {
+            PulseOrBeatValue::Sample(str::parse(s_sample)?)
+        } else if let Some(s_bar) = s.strip_prefix('b') {
+            let mut it = s_bar.split('.');
+            let mut next = |default| it.next().filter(|c| !c.is_empty()).unwrap_or(default);
+            let (bar, beat, tick): (u64, u64, u64) = (
+                str::parse(next("1"))?,
+                str::parse(next("1"))?,
+                str::parse(next("0"))?,
+            );
+            if it.next().is_some() {
+                return Err("bar positions can't have more than 3 components".into());
+            }
+            if bar == 0 || beat == 0 {
+                return Err("bars and beats are 1-based".into());
+            }
+            let (bar, beat) = (bar - 1, beat - 1);
+            PulseOrBeatValue::Bar(BarBeatTick { bar, beat, tick })
+        } else if s.matches(':').count() == 3 {
+            let (s_hms, s_subframes) = s.split_once('.').unwrap_or((s, "0"));
+            let mut it = s_hms.split(':');
+            let mut next = || it.next().filter(|c| !c.is_empty()).unwrap_or("0");
+            let tc = Timecode {
+                hours: str::parse(next())?,
+                minutes: str::parse(next())?,
+                seconds: str::parse(next())?,
+                frames: str::parse(next())?,
+                subframes: str::parse(s_subframes)?,
+            };
+            if tc.minutes >= 60 || tc.seconds >= 60 {
+                return Err("timecode minutes and seconds must be smaller than 60".into());
+            }
+            PulseOrBeatValue::Timecode(tc)
+        } else if let Some((s_qn, s_pulse)) = s.split_once(':') {
             let s_qn = if s_qn.is_empty() { "0" } else { s_qn };
             let s_pulse = if s_pulse.is_empty() { "0" } else { s_pulse };
             PulseOrBeatValue::Beat(
@@ -275,11 +958,12 @@ impl std::fmt::Display for PulseOrBeat {
 pub fn total_pulse_of_range(
     start: &PulseOrBeat,
     end: &Option<PulseOrBeat>,
-    timing: &Timing,
+    tempo_map: &TempoMap,
+    samplerate: Option<u32>,
 ) -> Result<(u64, Option<u64>), Box<dyn Error>> {
-    let start_pulse = start.total_pulse(timing)?;
+    let start_pulse = start.total_pulse(tempo_map, samplerate)?;
     let end_pulse = if let Some(end) = end {
-        let end_pulse = end.total_pulse(timing)?;
+        let end_pulse = end.total_pulse(tempo_map, samplerate)?;
         if start_pulse > end_pulse {
             return Err(format!(
                 "`{start}` (→ {start_pulse}) is bigger than `{end}` (→ {end_pulse})"
@@ -293,6 +977,585 @@ pub fn total_pulse_of_range(
     Ok((start_pulse, end_pulse))
 }
 
-pub fn duration(smf: &Smf, samplerate: Option<u32>) {
-    println!("{}", MidiTimeDisplay::new_at_end(smf, samplerate))
+/// Note-related bounds of a track or sequence.
+#[derive(Clone, Copy, Default)]
+struct NoteBounds {
+    end: u64,
+    first_note: Option<u64>,
+    last_note: Option<u64>,
+    last_note_off: Option<u64>,
+}
+
+impl NoteBounds {
+    fn of_track(track: &[TrackEvent]) -> Self {
+        let mut ret = NoteBounds::default();
+        for ev in track {
+            ret.end += ev.delta.as_int() as u64;
+            if event::note_on(ev).is_some() {
+                ret.first_note = ret.first_note.or(Some(ret.end));
+                ret.last_note = Some(ret.end);
+            } else if event::note_off(ev).is_some() {
+                ret.last_note_off = Some(ret.end);
+            }
+        }
+        ret
+    }
+
+    fn merge(self, other: Self) -> Self {
+        NoteBounds {
+            end: max(self.end, other.end),
+            first_note: match (self.first_note, other.first_note) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            last_note: max(self.last_note, other.last_note),
+            last_note_off: max(self.last_note_off, other.last_note_off),
+        }
+    }
+}
+
+/// Machine-readable length of a range.
+#[derive(Serialize)]
+struct SpanRecord {
+    pulses: u64,
+    realtime_us: f64,
+}
+
+#[derive(Serialize)]
+struct TrackDurationRecord {
+    end: TimeRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_note_off: Option<TimeRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_of_track_gap: Option<SpanRecord>,
+}
+
+#[derive(Serialize)]
+struct DurationRecord {
+    end: TimeRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_note: Option<TimeRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_note: Option<TimeRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leading_silence: Option<SpanRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_note_off: Option<TimeRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trailing_silence: Option<SpanRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracks: Option<Vec<TrackDurationRecord>>,
+}
+
+pub fn duration(
+    smf: &Smf,
+    tempo_map: &TempoMap,
+    samplerate: Option<u32>,
+    extended: bool,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let mut time = MidiTimeDisplay::new_at_end(tempo_map, smf, samplerate);
+    if !extended {
+        if format == Format::Json {
+            return Ok(output::print_json(&DurationRecord {
+                end: time.record(),
+                first_note: None,
+                last_note: None,
+                leading_silence: None,
+                last_note_off: None,
+                trailing_silence: None,
+                tracks: None,
+            })?);
+        }
+        println!("{time}");
+        return Ok(());
+    }
+
+    let tracks = smf
+        .tracks
+        .iter()
+        .map(|track| NoteBounds::of_track(track))
+        .collect::<Vec<_>>();
+    let seq = tracks
+        .iter()
+        .fold(NoteBounds::default(), |a, b| a.merge(*b));
+    let span = |start: u64, end: u64| {
+        let realtime = tempo_map.time_at(end).realtime - tempo_map.time_at(start).realtime;
+        (end - start, realtime)
+    };
+    let span_record = |start: u64, end: u64| {
+        let (pulses, realtime) = span(start, end);
+        SpanRecord {
+            pulses,
+            realtime_us: realtime.as_micros_f64(),
+        }
+    };
+
+    if format == Format::Json {
+        let mut record_at = |pulse: u64| {
+            time.time = tempo_map.time_at(pulse);
+            time.record()
+        };
+        let record = DurationRecord {
+            end: record_at(seq.end),
+            first_note: seq.first_note.map(&mut record_at),
+            last_note: seq.last_note.map(&mut record_at),
+            leading_silence: seq.first_note.map(|first| span_record(0, first)),
+            last_note_off: seq.last_note_off.map(&mut record_at),
+            trailing_silence: seq.last_note_off.map(|off| span_record(off, seq.end)),
+            tracks: Some(
+                tracks
+                    .iter()
+                    .map(|track| TrackDurationRecord {
+                        end: record_at(track.end),
+                        last_note_off: track.last_note_off.map(&mut record_at),
+                        end_of_track_gap: track
+                            .last_note_off
+                            .map(|off| span_record(off, track.end)),
+                    })
+                    .collect(),
+            ),
+        };
+        return Ok(output::print_json(&record)?);
+    }
+
+    let span = |start: u64, end: u64| {
+        let (pulses, realtime) = span(start, end);
+        format!("{pulses} pulses / {realtime}")
+    };
+    let mut print_at = |label: &str, pulse: u64| {
+        time.time = tempo_map.time_at(pulse);
+        println!("{label:>16}: {time}");
+    };
+
+    print_at("End", seq.end);
+    if let Some((first_note, last_note)) = seq.first_note.zip(seq.last_note) {
+        print_at("First note", first_note);
+        print_at("Last note", last_note);
+        println!("{:>16}: {}", "Leading silence", span(0, first_note));
+    } else {
+        println!("No notes.");
+    }
+    if let Some(last_note_off) = seq.last_note_off {
+        print_at("Last Note Off", last_note_off);
+        println!(
+            "{:>16}: {}",
+            "Trailing silence",
+            span(last_note_off, seq.end)
+        );
+    }
+
+    for (track_i, track) in tracks.iter().enumerate() {
+        println!("\nTrack #{track_i}:");
+        print_at("End", track.end);
+        if let Some(last_note_off) = track.last_note_off {
+            print_at("Last Note Off", last_note_off);
+            println!(
+                "{:>16}: {}",
+                "End of Track gap",
+                span(last_note_off, track.end)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TimeSignatureRecord {
+    numerator: u8,
+    denominator: u64,
+}
+
+#[derive(Serialize)]
+struct TrackPositionRecord {
+    /// Index of the first event at or after the position, `None` if the track ends before.
+    event: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pulse: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct PositionRecord {
+    input: String,
+    time: TimeRecord,
+    qn_micros: u64,
+    bpm: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_signature: Option<TimeSignatureRecord>,
+    tracks: Vec<TrackPositionRecord>,
+}
+
+pub fn at(
+    smf: &Smf,
+    tempo_map: &TempoMap,
+    positions: &[PulseOrBeat],
+    samplerate: Option<u32>,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let mut time = MidiTimeDisplay::new_at_end(tempo_map, smf, samplerate);
+    let mut records = vec![];
+    for pos in positions {
+        let pulse = pos.resolve(tempo_map, samplerate)?;
+        time.time = tempo_map.time_at(pulse);
+        let tracks = smf
+            .tracks
+            .iter()
+            .map(|track| {
+                let mut pulse_cur = 0;
+                let event = track.iter().position(|ev| {
+                    pulse_cur += ev.delta.as_int() as u64;
+                    pulse_cur >= pulse
+                });
+                let pulse = event.map(|_| pulse_cur);
+                TrackPositionRecord { event, pulse }
+            })
+            .collect();
+        records.push(PositionRecord {
+            input: pos.to_string(),
+            time: time.record(),
+            qn_micros: time.time.qn_micros(),
+            bpm: time.time.bpm(),
+            time_signature: tempo_map
+                .time_signature_at(pulse)
+                .map(|(numerator, denominator)| TimeSignatureRecord {
+                    numerator,
+                    denominator,
+                }),
+            tracks,
+        });
+    }
+
+    if format == Format::Json {
+        #[derive(Serialize)]
+        struct Output {
+            positions: Vec<PositionRecord>,
+        }
+        return Ok(output::print_json(&Output { positions: records })?);
+    }
+
+    let track_width = (max(smf.tracks.len(), 1).ilog10() + 1) as usize;
+    let event_width = smf
+        .tracks
+        .iter()
+        .map(|track| (max(track.len(), 1).ilog10() + 1) as usize)
+        .max()
+        .unwrap_or(1);
+    for (pos_i, record) in records.iter().enumerate() {
+        time.time = tempo_map.time_at(record.time.pulse);
+        if pos_i != 0 {
+            println!();
+        }
+        println!("{}: {time}", record.input);
+        let (qn_micros, bpm) = (record.qn_micros, record.bpm);
+        println!("  Tempo: {qn_micros} µs per quarter note ({bpm:.3} BPM)");
+        if let Some(TimeSignatureRecord {
+            numerator,
+            denominator,
+        }) = &record.time_signature
+        {
+            println!("  Time signature: {numerator}/{denominator}");
+        }
+        for (track_i, track) in record.tracks.iter().enumerate() {
+            match (track.event, track.pulse) {
+                (Some(ev_i), Some(pulse)) => println!(
+                    "  Track #{track_i:<track_width$}: event {ev_i:>event_width$} (at pulse {pulse})"
+                ),
+                _ => println!("  Track #{track_i:<track_width$}: after the last event"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of the key with the given number of sharps (positive) or flats (negative).
+fn key_name(sharps: i8, minor: bool) -> String {
+    const MAJOR: [&str; 15] = [
+        "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+    ];
+    const MINOR: [&str; 15] = [
+        "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+    ];
+    let names = if minor { &MINOR } else { &MAJOR };
+    let mode = if minor { "minor" } else { "major" };
+    match names.get((sharps as isize + 7) as usize) {
+        Some(name) => format!("{name} {mode}"),
+        None => format!("{sharps:+} accidentals, {mode}"),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TempoMapEventKind {
+    Tempo { qn_micros: u64, bpm: f64 },
+    TimeSignature { numerator: u8, denominator: u64 },
+    KeySignature { sharps: i8, minor: bool },
+}
+
+impl std::fmt::Display for TempoMapEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TempoMapEventKind::Tempo { qn_micros, bpm } => {
+                write!(f, "Tempo: {qn_micros} µs per quarter note ({bpm:.3} BPM)")
+            }
+            TempoMapEventKind::TimeSignature {
+                numerator,
+                denominator,
+            } => write!(f, "Time signature: {numerator}/{denominator}"),
+            TempoMapEventKind::KeySignature { sharps, minor } => {
+                write!(f, "Key signature: {}", key_name(*sharps, *minor))
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TempoMapEvent {
+    track: usize,
+    time: TimeRecord,
+    length_pulses: u64,
+    length_realtime_us: f64,
+    #[serde(flatten)]
+    kind: TempoMapEventKind,
+}
+
+pub fn tempo_map(
+    smf: &Smf,
+    tempo_map: &TempoMap,
+    samplerate: Option<u32>,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let mut events = vec![];
+    for (track_i, track) in smf.tracks.iter().enumerate() {
+        let mut pulse = 0;
+        for ev in track {
+            pulse += ev.delta.as_int() as u64;
+            let kind = match ev.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    let qn_micros = tempo.as_int() as u64;
+                    let bpm = 60_000_000.0 / (qn_micros as f64);
+                    TempoMapEventKind::Tempo { qn_micros, bpm }
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(num, den_pow2, _, _)) => {
+                    TempoMapEventKind::TimeSignature {
+                        numerator: num,
+                        denominator: 1_u64.checked_shl(den_pow2.into()).unwrap_or(0),
+                    }
+                }
+                TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor)) => {
+                    TempoMapEventKind::KeySignature { sharps, minor }
+                }
+                _ => continue,
+            };
+            events.push((track_i, pulse, kind));
+        }
+    }
+
+    // The sort is stable, which preserves the track order of simultaneous events.
+    events.sort_by_key(|(_, pulse, _)| *pulse);
+
+    // Each section lasts until the next event of the same type, or the end of the sequence.
+    let end = tempo_map.time_at_end(smf);
+    let mut time = MidiTimeDisplay::new_at_end(tempo_map, smf, samplerate);
+    let events = events
+        .iter()
+        .enumerate()
+        .map(|(i, (track, pulse, kind))| {
+            let next = events
+                .iter()
+                .skip(i + 1)
+                .find(|(_, _, other)| std::mem::discriminant(kind) == std::mem::discriminant(other))
+                .map_or(end, |(_, pulse, _)| tempo_map.time_at(*pulse));
+            time.time = tempo_map.time_at(*pulse);
+            let length_realtime = next.realtime - time.time.realtime;
+            (
+                *track,
+                time.record(),
+                next.pulse - pulse,
+                length_realtime,
+                *kind,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if format == Format::Json {
+        let events = events
+            .into_iter()
+            .map(
+                |(track, time, length_pulses, length_realtime, kind)| TempoMapEvent {
+                    track,
+                    time,
+                    length_pulses,
+                    length_realtime_us: length_realtime.as_micros_f64(),
+                    kind,
+                },
+            )
+            .collect::<Vec<_>>();
+        #[derive(Serialize)]
+        struct Output {
+            events: Vec<TempoMapEvent>,
+        }
+        return Ok(output::print_json(&Output { events })?);
+    }
+
+    let widths = time.widths();
+    let track_header = "Track";
+    let pulse_header = "Pulse";
+    let beat_header = match tempo_map.timing() {
+        Timing::Metrical(_) => "Beat",
+        Timing::Timecode(_, _) => "Timecode",
+    };
+    let bar_header = "Bar";
+    let realtime_header = "Realtime";
+    let sample_header = "Sample";
+    let length_header = "Length";
+    let length_realtime_header = "Length (realtime)";
+    let track_width = max(
+        track_header.len(),
+        (max(smf.tracks.len(), 1).ilog10() + 1) as usize,
+    );
+    let pulse_width = max(pulse_header.len(), widths.pulse);
+    let beat_width = max(beat_header.len(), widths.beat);
+    let bar_width = max(bar_header.len(), widths.bar);
+    let realtime_width = max(realtime_header.len(), widths.realtime);
+    let sample_width = max(sample_header.len(), widths.sample);
+    let length_width = max(length_header.len(), widths.pulse);
+    let length_realtime_width = max(length_realtime_header.len(), widths.realtime);
+    let has_bars = time.display_bar().is_some();
+
+    print!(
+        "{track_header:>track_width$}\t{pulse_header:>pulse_width$}\t{beat_header:>beat_width$}"
+    );
+    if has_bars {
+        print!("\t{bar_header:>bar_width$}");
+    }
+    print!("\t{realtime_header:>realtime_width$}");
+    if samplerate.is_some() {
+        print!("\t{sample_header:>sample_width$}");
+    }
+    println!(
+        "\t{length_header:>length_width$}\t{length_realtime_header:>length_realtime_width$}\tEvent"
+    );
+    for (track, record, length, length_realtime, kind) in events {
+        time.time = tempo_map.time_at(record.pulse);
+        print!(
+            "{track:>track_width$}\t{:>pulse_width$}\t{:>beat_width$}",
+            time.display_pulse(),
+            time.display_beat()
+        );
+        if let Some(bar) = time.display_bar() {
+            print!("\t{bar:>bar_width$}");
+        }
+        print!("\t{:>realtime_width$}", time.display_realtime());
+        if let Some(sample) = time.display_sample() {
+            print!("\t{sample:>sample_width$}");
+        }
+        println!("\t{length:>length_width$}\t{length_realtime:>length_realtime_width$}\t{kind}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_frame_labels() {
+        // The first two frame labels of every minute are skipped, except for every 10th minute.
+        assert_eq!(df_frame_label(0), 0);
+        assert_eq!(df_frame_label(1799), 1799);
+        assert_eq!(df_frame_label(1800), 1802);
+        assert_eq!(df_frame_label(DF_FRAMES_PER_10_MINUTES - 1), 17999);
+        assert_eq!(df_frame_label(DF_FRAMES_PER_10_MINUTES), 18000);
+        assert_eq!(df_frame_label(DF_FRAMES_PER_10_MINUTES + 1800), 19802);
+
+        let tc = Timecode::from_pulse(1800, Fps::Fps29, 1);
+        assert_eq!((tc.minutes, tc.seconds, tc.frames), (1, 0, 2));
+    }
+
+    #[test]
+    fn drop_frame_round_trip() {
+        for pulse in 0..(2 * DF_FRAMES_PER_10_MINUTES * 4 + 100) {
+            let tc = Timecode::from_pulse(pulse, Fps::Fps29, 4);
+            assert_eq!(tc.total_pulse(Fps::Fps29, 4), Ok(pulse), "{tc:?}");
+        }
+    }
+
+    #[test]
+    fn drop_frame_skipped_labels() {
+        let tc = |minutes, frames| Timecode {
+            hours: 0,
+            minutes,
+            seconds: 0,
+            frames,
+            subframes: 0,
+        };
+        assert!(tc(1, 0).total_pulse(Fps::Fps29, 1).is_err());
+        assert!(tc(1, 1).total_pulse(Fps::Fps29, 1).is_err());
+        assert_eq!(tc(1, 2).total_pulse(Fps::Fps29, 1), Ok(1800));
+        assert_eq!(
+            tc(10, 0).total_pulse(Fps::Fps29, 1),
+            Ok(DF_FRAMES_PER_10_MINUTES)
+        );
+        assert_eq!(tc(1, 0).total_pulse(Fps::Fps30, 1), Ok(1800));
+    }
+
+    fn realtime(num: u128, den: u64) -> Realtime {
+        Realtime { num, den }
+    }
+
+    #[test]
+    fn realtime_ordering() {
+        assert_eq!(realtime(1, 2), realtime(240, 480));
+        assert!(realtime(1, 3) < realtime(1, 2));
+        assert!(realtime(1000, 1) > realtime(999_999, 1000));
+    }
+
+    #[test]
+    fn realtime_sub() {
+        let diff = realtime(3, 2) - realtime(1, 3);
+        assert_eq!((diff.num, diff.den), (7, 6));
+
+        // Values with the same denominator keep it.
+        let diff = (realtime(4800, 480) - realtime(960, 480)) - realtime(480, 480);
+        assert_eq!((diff.num, diff.den), (3360, 480));
+
+        let diff = realtime(500, 480) - realtime(500, 480);
+        assert_eq!(diff.num, 0);
+    }
+
+    #[test]
+    fn realtime_rounding() {
+        assert_eq!(realtime(999_499, 1).millis(), 999);
+        assert_eq!(realtime(999_500, 1).millis(), 1000);
+        assert_eq!(realtime(999_500, 1).to_string(), "0:01:000m");
+        assert_eq!(realtime(59_999_500, 1).to_string(), "1:00:000m");
+        assert_eq!(realtime(1_000_000, 1).sample(44100), 44100);
+        assert_eq!(realtime(1, 1).sample(44100), 0);
+
+        // 30 frames at 29.97 FPS take 1.001 seconds.
+        assert_eq!(timecode_realtime(Fps::Fps29, 4, 30 * 4).millis(), 1001);
+    }
+
+    #[test]
+    fn timecode_pulse_at_realtime() {
+        // Tempo events don't affect SMPTE timing, but still show up in the tempo map.
+        let mut smf = Smf::new(midly::Header::new(
+            midly::Format::SingleTrack,
+            Timing::Timecode(Fps::Fps25, 40),
+        ));
+        smf.tracks.push(vec![
+            TrackEvent {
+                delta: 1000.into(),
+                kind: TrackEventKind::Meta(MetaMessage::Tempo(400_000.into())),
+            },
+            TrackEvent {
+                delta: 100_000.into(),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            },
+        ]);
+        let tempo_map = TempoMap::new(&smf);
+        assert_eq!(tempo_map.pulse_at_realtime(realtime(500_000, 1)), 500);
+        assert_eq!(tempo_map.pulse_at_realtime(realtime(1_000_000, 1)), 1000);
+        assert_eq!(tempo_map.pulse_at_realtime(realtime(60_000_000, 1)), 60000);
+    }
 }